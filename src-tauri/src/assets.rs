@@ -0,0 +1,82 @@
+use std::path::{Path, PathBuf};
+
+use tracing::debug;
+
+use crate::http::{http_response, http_response_bytes, safe_decode};
+
+// 静态资源的路径前缀，成功页面可以通过 /static/logo.png 等地址引用资源目录中的文件
+pub const STATIC_PREFIX: &str = "/static/";
+
+// 单个静态资源允许的最大字节数
+const MAX_ASSET_BYTES: u64 = 5 * 1024 * 1024;
+
+// 返回资源目录中对应文件的响应，文件不存在或路径不合法时一律返回 404
+pub async fn serve(dir: &Path, request_path: &str) -> Vec<u8> {
+    let Some(path) = resolve(dir, request_path) else {
+        debug!(path = %request_path, "Rejected static asset path");
+        return not_found();
+    };
+    
+    // 符号链接可能指向目录之外，以规范化后的路径再确认一次
+    let inside = match (tokio::fs::canonicalize(&path).await, tokio::fs::canonicalize(dir).await) {
+        (Ok(path), Ok(dir)) => path.starts_with(dir),
+        _ => false,
+    };
+    let servable = tokio::fs::metadata(&path)
+        .await
+        .is_ok_and(|metadata| metadata.is_file() && metadata.len() <= MAX_ASSET_BYTES);
+    if !inside || !servable {
+        return not_found();
+    }
+    
+    match tokio::fs::read(&path).await {
+        Ok(body) => http_response_bytes("200 OK", &[("Cache-Control", "no-store")], content_type(&path), &body),
+        Err(e) => {
+            debug!(path = %path.display(), error = %e, "Failed to read static asset");
+            not_found()
+        }
+    }
+}
+
+// 把 /static/ 之后的路径映射到资源目录下，拒绝 `..`、绝对路径和 Windows 盘符，防止读取目录以外的文件
+fn resolve(dir: &Path, request_path: &str) -> Option<PathBuf> {
+    let relative = request_path.strip_prefix(STATIC_PREFIX)?;
+    let relative = safe_decode(relative);
+    
+    let mut path = dir.to_path_buf();
+    for segment in relative.split('/') {
+        if segment.is_empty() || segment == "." || segment == ".." || segment.contains(['\\', ':']) {
+            return None;
+        }
+        path.push(segment);
+    }
+    Some(path)
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        Some("txt") => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+fn not_found() -> Vec<u8> {
+    http_response("404 Not Found", &[], "text/plain; charset=utf-8", "Not Found").into_bytes()
+}
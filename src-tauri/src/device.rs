@@ -0,0 +1,203 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tauri::{command, AppHandle, Emitter, State};
+use tracing::warn;
+
+use crate::client::HttpClient;
+use crate::error::OAuthError;
+use crate::sink::CallbackSink;
+use crate::token;
+
+const DEVICE_CODE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+// 提供商未返回 interval 时的默认轮询间隔（秒），与 RFC 8628 一致
+const DEFAULT_POLL_INTERVAL_SECS: u64 = 5;
+
+// 收到 slow_down 时轮询间隔增加的秒数
+const SLOW_DOWN_STEP_SECS: u64 = 5;
+
+// 调用方未传入 expires_in 时设备码的有效期（秒），与常见提供商的默认值一致
+const DEFAULT_EXPIRES_IN_SECS: u64 = 900;
+
+// 设备授权端点的响应，前端据此向用户展示 user_code 和验证地址
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceFlowInit {
+    pub device_code: String,
+    pub user_code: String,
+    // Google 等提供商使用 verification_url 字段名
+    #[serde(alias = "verification_url")]
+    pub verification_uri: String,
+    #[serde(default)]
+    pub verification_uri_complete: Option<String>,
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+}
+
+fn default_interval() -> u64 {
+    DEFAULT_POLL_INTERVAL_SECS
+}
+
+// 发起设备授权流程，并通过 oauth-device-code 事件通知前端展示 user_code
+#[command]
+pub async fn start_device_flow(
+    device_auth_url: String,
+    client_id: String,
+    scopes: Vec<String>,
+    client: State<'_, HttpClient>,
+    app: AppHandle,
+) -> Result<DeviceFlowInit, OAuthError> {
+    client.check_trusted(&device_auth_url)?;
+    let scope = scopes.join(" ");
+    let mut form = vec![("client_id", client_id.as_str())];
+    if !scope.is_empty() {
+        form.push(("scope", &scope));
+    }
+    
+    let (status, body) = token::post_form(&client.get(), &device_auth_url, &form).await?;
+    if !status.is_success() {
+        return Err(OAuthError::Provider {
+            status: Some(status.as_u16()),
+            body,
+        });
+    }
+    
+    let init: DeviceFlowInit = serde_json::from_str(&body)
+        .map_err(|e| format!("invalid device authorization response: {}", e))?;
+    
+    if let Err(e) = Emitter::emit(&app, "oauth-device-code", &init) {
+        warn!(error = %e, "Failed to emit oauth-device-code event");
+    }
+    
+    Ok(init)
+}
+
+// 按设备码授权类型轮询令牌端点，直到用户完成授权、拒绝或设备码过期
+// authorization_pending 继续等待，slow_down 按规范延长轮询间隔
+// expires_in 传入 start_device_flow 返回的有效期，超过后不再轮询并返回 token_expired，
+// 即使提供商一直不返回 expired_token；省略时按 DEFAULT_EXPIRES_IN_SECS 计算
+#[command]
+pub async fn poll_device_token(
+    token_url: String,
+    client_id: String,
+    device_code: String,
+    interval: Option<u64>,
+    expires_in: Option<u64>,
+    client: State<'_, HttpClient>,
+    app: AppHandle,
+) -> Result<Value, OAuthError> {
+    client.check_trusted(&token_url)?;
+    let interval = interval.unwrap_or(DEFAULT_POLL_INTERVAL_SECS).max(1);
+    let expires_in = Duration::from_secs(expires_in.unwrap_or(DEFAULT_EXPIRES_IN_SECS));
+    poll_token(&client.get(), &token_url, &client_id, &device_code, interval, expires_in, &app).await
+}
+
+async fn poll_token(
+    client: &reqwest::Client,
+    token_url: &str,
+    client_id: &str,
+    device_code: &str,
+    mut interval: u64,
+    expires_in: Duration,
+    sink: &dyn CallbackSink,
+) -> Result<Value, OAuthError> {
+    let form = [
+        ("grant_type", DEVICE_CODE_GRANT),
+        ("device_code", device_code),
+        ("client_id", client_id),
+    ];
+    let deadline = Instant::now() + expires_in;
+    let mut attempt: u64 = 0;
+    
+    loop {
+        // 下一次轮询已超过有效期时直接返回，不再等待
+        if Instant::now() + Duration::from_secs(interval) > deadline {
+            return Err(OAuthError::TokenExpired(format!(
+                "expired_token: device code expired after {} seconds",
+                expires_in.as_secs()
+            )));
+        }
+        tokio::time::sleep(Duration::from_secs(interval)).await;
+        attempt += 1;
+        
+        // GitHub 在等待授权期间返回 200 和 error 字段，因此不能只看状态码
+        let (status, body) = token::post_form(client, token_url, &form).await?;
+        match token::provider_error(&body).as_deref() {
+            Some("authorization_pending") => {}
+            Some("slow_down") => interval += SLOW_DOWN_STEP_SECS,
+            Some("expired_token") => return Err(OAuthError::TokenExpired(format!("expired_token: {}", body))),
+            Some(_) => {
+                return Err(OAuthError::Provider {
+                    status: Some(status.as_u16()),
+                    body,
+                })
+            }
+            None if status.is_success() => {
+                return serde_json::from_str(&body).map_err(|e| format!("invalid token response: {}", e).into());
+            }
+            None => {
+                return Err(OAuthError::Provider {
+                    status: Some(status.as_u16()),
+                    body,
+                })
+            }
+        }
+        
+        let payload = json!({
+            "device_code": device_code,
+            "attempt": attempt,
+            "interval": interval
+        });
+        if let Err(e) = sink.emit("oauth-device-pending", &payload) {
+            warn!(error = %e, "Failed to emit oauth-device-pending event");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    
+    use tokio::net::TcpListener;
+    
+    use super::*;
+    use crate::http::{http_response, parse_http_request, read_body, read_request_head, write_response, RequestHead};
+    use crate::test_support::RecordingSink;
+    
+    // 令牌端点永远返回 authorization_pending，也从不返回 expired_token
+    async fn pending_token_endpoint() -> String {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let url = format!("http://{}/token", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let Ok(RequestHead::Complete(buffer)) = read_request_head(&mut stream, 16 * 1024).await else {
+                    continue;
+                };
+                if let Some(length) = parse_http_request(&buffer).ok().and_then(|request| request.content_length()) {
+                    let _ = read_body(&mut stream, &buffer, length).await;
+                }
+                let body = r#"{"error":"authorization_pending"}"#;
+                write_response(&mut stream, &http_response("400 Bad Request", &[], "application/json", body)).await;
+            }
+        });
+        url
+    }
+    
+    #[tokio::test]
+    async fn polling_stops_when_the_device_code_expires() {
+        let token_url = pending_token_endpoint().await;
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        let sink = RecordingSink::default();
+        
+        let polled = poll_token(&client, &token_url, "client", "device", 1, Duration::from_secs(2), &sink);
+        let error = tokio::time::timeout(Duration::from_secs(5), polled)
+            .await
+            .expect("polling did not stop at expires_in")
+            .unwrap_err();
+        assert!(matches!(error, OAuthError::TokenExpired(_)), "{:?}", error);
+        assert_eq!(sink.events("oauth-device-pending").len(), 1);
+    }
+}
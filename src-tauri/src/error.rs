@@ -0,0 +1,126 @@
+use std::fmt;
+
+use serde::{Serialize, Serializer};
+
+// 命令返回给前端的错误，前端可以按 kind 区分错误类型，message 为人类可读的描述：
+// { "kind": "port_in_use" | "not_found" | "state_mismatch" | "token_expired" | "network"
+//           | "provider" | "cancelled" | "untrusted_host" | "too_many_servers"
+//           | "invalid_signature" | "invalid_claims" | "internal",
+//   "message": string,
+//   "status": number,  // 仅 provider，提供商返回的 HTTP 状态码（没有时省略）
+//   "body": string }   // 仅 provider，提供商返回的响应内容
+#[derive(Debug, Clone)]
+pub enum OAuthError {
+    // 端口已被占用或已有服务器在运行
+    PortInUse(String),
+    // 指定的服务器、流程或令牌不存在
+    NotFound(String),
+    // 回调中的 state 与发起授权时不一致
+    StateMismatch(String),
+    // 访问令牌、刷新令牌或授权码已失效，需要重新登录
+    TokenExpired(String),
+    // 无法连接提供商或读取响应
+    Network(String),
+    // 提供商返回了错误
+    Provider { status: Option<u16>, body: String },
+    // 用户取消了登录流程
+    Cancelled,
+    // 请求地址的主机不在 register_trusted_hosts 登记的列表中
+    UntrustedHost(String),
+    // 同时运行的回调服务器数量已达上限
+    TooManyServers(String),
+    // ID Token 签名校验失败
+    InvalidSignature(String),
+    // ID Token 的 iss 或 aud 与预期不符
+    InvalidClaims(String),
+    Internal(String),
+}
+
+impl OAuthError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            OAuthError::PortInUse(_) => "port_in_use",
+            OAuthError::NotFound(_) => "not_found",
+            OAuthError::StateMismatch(_) => "state_mismatch",
+            OAuthError::TokenExpired(_) => "token_expired",
+            OAuthError::Network(_) => "network",
+            OAuthError::Provider { .. } => "provider",
+            OAuthError::Cancelled => "cancelled",
+            OAuthError::UntrustedHost(_) => "untrusted_host",
+            OAuthError::TooManyServers(_) => "too_many_servers",
+            OAuthError::InvalidSignature(_) => "invalid_signature",
+            OAuthError::InvalidClaims(_) => "invalid_claims",
+            OAuthError::Internal(_) => "internal",
+        }
+    }
+}
+
+impl fmt::Display for OAuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OAuthError::PortInUse(message)
+            | OAuthError::NotFound(message)
+            | OAuthError::StateMismatch(message)
+            | OAuthError::TokenExpired(message)
+            | OAuthError::Network(message)
+            | OAuthError::UntrustedHost(message)
+            | OAuthError::TooManyServers(message)
+            | OAuthError::InvalidSignature(message)
+            | OAuthError::InvalidClaims(message)
+            | OAuthError::Internal(message) => write!(f, "{}", message),
+            OAuthError::Provider { status: Some(status), body } => {
+                write!(f, "provider returned {}: {}", status, body)
+            }
+            OAuthError::Provider { status: None, body } => write!(f, "provider returned an error: {}", body),
+            OAuthError::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for OAuthError {}
+
+impl Serialize for OAuthError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Serialized<'a> {
+            kind: &'a str,
+            message: String,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            status: Option<u16>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            body: Option<&'a str>,
+        }
+        
+        let (status, body) = match self {
+            OAuthError::Provider { status, body } => (*status, Some(body.as_str())),
+            _ => (None, None),
+        };
+        Serialized {
+            kind: self.kind(),
+            message: self.to_string(),
+            status,
+            body,
+        }
+        .serialize(serializer)
+    }
+}
+
+// 未分类的字符串错误视为内部错误
+impl From<String> for OAuthError {
+    fn from(message: String) -> Self {
+        OAuthError::Internal(message)
+    }
+}
+
+impl From<&str> for OAuthError {
+    fn from(message: &str) -> Self {
+        OAuthError::Internal(message.to_string())
+    }
+}
+
+// 兼容仍然使用字符串错误的调用方
+impl From<OAuthError> for String {
+    fn from(error: OAuthError) -> Self {
+        error.to_string()
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::{command, AppHandle, Manager};
+
+use crate::error::OAuthError;
+use crate::lock_recover;
+use crate::private_file;
+
+// 应用数据目录下保存进行中流程的文件
+const FLOWS_FILE: &str = "oauth_flows.json";
+
+// 超过该时间的流程在恢复时被清除，授权码和 code_verifier 此时通常已失效
+const FLOW_TTL: Duration = Duration::from_secs(60 * 60);
+
+// 串行化对流程文件的读写，避免并发调用互相覆盖
+static FLOWS_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PersistedFlow {
+    saved_at: u64,
+    flow: Value,
+}
+
+// 保存进行中的流程（state、code_verifier、provider、port 等），应用重启后可以继续完成回调
+#[command]
+pub fn persist_oauth_flow(flow_id: String, state_json: Value, app: AppHandle) -> Result<(), OAuthError> {
+    if !state_json.is_object() {
+        return Err("flow state must be a JSON object".into());
+    }
+    
+    let _guard = lock_recover(&FLOWS_LOCK);
+    let path = flows_path(&app)?;
+    let mut flows = read_flows(&path)?;
+    flows.insert(
+        flow_id,
+        PersistedFlow {
+            saved_at: now_secs(),
+            flow: state_json,
+        },
+    );
+    Ok(write_flows(&path, &flows)?)
+}
+
+// 恢复之前保存的流程，同时清除已过期的流程
+#[command]
+pub fn restore_oauth_flow(flow_id: String, app: AppHandle) -> Result<Option<Value>, OAuthError> {
+    let _guard = lock_recover(&FLOWS_LOCK);
+    let path = flows_path(&app)?;
+    let mut flows = read_flows(&path)?;
+    
+    let now = now_secs();
+    let count = flows.len();
+    flows.retain(|_, persisted| now.saturating_sub(persisted.saved_at) < FLOW_TTL.as_secs());
+    if flows.len() != count {
+        write_flows(&path, &flows)?;
+    }
+    
+    Ok(flows.get(&flow_id).map(|persisted| persisted.flow.clone()))
+}
+
+// 删除全部保存的流程，返回删除的流程数
+pub fn clear_flows(app: &AppHandle) -> Result<usize, String> {
+    let _guard = lock_recover(&FLOWS_LOCK);
+    let path = flows_path(app)?;
+    let count = read_flows(&path).map(|flows| flows.len()).unwrap_or_default();
+    match std::fs::remove_file(&path) {
+        Ok(()) => Ok(count),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(format!("failed to remove flow file: {}", e)),
+    }
+}
+
+fn flows_path(app: &AppHandle) -> Result<PathBuf, String> {
+    let dir = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("failed to resolve app data directory: {}", e))?;
+    Ok(dir.join(FLOWS_FILE))
+}
+
+// 文件不存在视为没有保存的流程
+fn read_flows(path: &Path) -> Result<HashMap<String, PersistedFlow>, String> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content).map_err(|e| format!("invalid flow file: {}", e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(e) => Err(format!("failed to read flow file: {}", e)),
+    }
+}
+
+// 流程中含有 code_verifier，与待送达的回调载荷一样按私有文件写入
+fn write_flows(path: &Path, flows: &HashMap<String, PersistedFlow>) -> Result<(), String> {
+    let content = serde_json::to_string(flows).map_err(|e| e.to_string())?;
+    private_file::write_private(path, content.as_bytes(), "flow file")
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[cfg(unix)]
+    #[test]
+    fn flow_file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+        
+        let path = std::env::temp_dir().join(format!("oauth-flows-test-{}", std::process::id())).join(FLOWS_FILE);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "{}").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        
+        // 覆盖已有文件时也不沿用旧文件的权限
+        write_flows(&path, &HashMap::new()).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{}");
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,452 @@
+use std::collections::HashMap;
+use std::fmt;
+use serde_json::{json, Map, Value};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+// 解析后的回调请求
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub method: String,
+    // 请求行中的原始目标，包含查询字符串
+    pub target: String,
+    pub path: String,
+    // 同一个键可能出现多次（如多个 scope），按键首次出现的顺序保存，每个键保留全部取值
+    pub query: Vec<(String, Vec<String>)>,
+    // 原始查询字符串，没有 `?` 时为 None，用于重复回调检测
+    pub raw_query: Option<String>,
+    // 请求头名称统一转为小写
+    pub headers: HashMap<String, String>,
+}
+
+impl HttpRequest {
+    // response_mode=form_post 的回调以 POST 表单提交参数
+    pub fn is_form_post(&self) -> bool {
+        self.method == "POST"
+            && self
+                .header("Content-Type")
+                .and_then(|value| value.split(';').next())
+                .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/x-www-form-urlencoded"))
+    }
+    
+    pub fn content_length(&self) -> Option<usize> {
+        self.header("Content-Length")?.trim().parse().ok()
+    }
+    
+    // 用表单请求体中的参数代替查询参数，后续处理与 GET 回调一致
+    pub fn set_form_body(&mut self, body: &[u8]) {
+        self.query = parse_query(body);
+        self.raw_query = Some(text(body));
+    }
+    
+    // 取查询参数的第一个值
+    pub fn query_param(&self, key: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(name, _)| name == key)
+            .and_then(|(_, values)| values.first())
+            .map(String::as_str)
+    }
+    
+    // 按原顺序转换为 JSON 对象，作为事件中的 raw_params
+    pub fn query_json(&self) -> Value {
+        let params: Map<String, Value> = self
+            .query
+            .iter()
+            .map(|(key, values)| (key.clone(), json!(values)))
+            .collect();
+        Value::Object(params)
+    }
+    
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_ascii_lowercase()).map(String::as_str)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    // 连接在发送请求行之前就关闭了
+    Empty,
+    // 请求行缺少方法或路径，或方法、路径的格式不正确
+    MalformedRequestLine,
+    // 请求行中的协议版本不是 HTTP/1.0 或 HTTP/1.1
+    UnsupportedVersion(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "empty request"),
+            ParseError::MalformedRequestLine => write!(f, "malformed request line"),
+            ParseError::UnsupportedVersion(version) => write!(f, "unsupported HTTP version: {}", version),
+        }
+    }
+}
+
+// 解析请求行和请求头，请求体（如果有）被忽略
+// 按字节在 ASCII 分隔符处切分，参数值先百分号解码再按 UTF-8 解码，避免提前做有损转换破坏取值
+pub fn parse_http_request(raw: &[u8]) -> Result<HttpRequest, ParseError> {
+    let mut lines = raw.split(|&b| b == b'\n').map(|line| line.strip_suffix(b"\r").unwrap_or(line));
+    let request_line = lines.next().filter(|line| !line.is_empty()).ok_or(ParseError::Empty)?;
+    
+    let (method, target) = parse_request_line(request_line)?;
+    
+    let (path, raw_query) = match split_once(target, b'?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (target, None),
+    };
+    
+    // 空行之后是请求体；无法识别的请求头行直接跳过
+    let headers = lines
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| split_once(line, b':'))
+        .map(|(name, value)| (text(name).trim().to_ascii_lowercase(), text(value).trim().to_string()))
+        .collect();
+    
+    Ok(HttpRequest {
+        method: text(method),
+        target: text(target),
+        path: text(path),
+        query: raw_query.map(parse_query).unwrap_or_default(),
+        raw_query: raw_query.map(text),
+        headers,
+    })
+}
+
+// 把请求行拆分为方法、路径和可选的协议版本，只接受 HTTP/1.0 和 HTTP/1.1，缺少版本时按 HTTP/1.0 处理
+// 方法必须是大写字母，路径必须以 `/` 开头，这样 `GET` 或 `/callback/github` 这样缺少一部分的请求行会被拒绝
+fn parse_request_line(line: &[u8]) -> Result<(&[u8], &[u8]), ParseError> {
+    let parts: Vec<&[u8]> = line.split(u8::is_ascii_whitespace).filter(|part| !part.is_empty()).collect();
+    let (method, target, version) = match parts.as_slice() {
+        [method, target] => (*method, *target, None),
+        [method, target, version] => (*method, *target, Some(*version)),
+        _ => return Err(ParseError::MalformedRequestLine),
+    };
+    
+    if !method.iter().all(u8::is_ascii_uppercase) || !target.starts_with(b"/") {
+        return Err(ParseError::MalformedRequestLine);
+    }
+    if let Some(version) = version {
+        if version != b"HTTP/1.0" && version != b"HTTP/1.1" {
+            return Err(ParseError::UnsupportedVersion(text(version)));
+        }
+    }
+    Ok((method, target))
+}
+
+// 解析查询字符串，重复的键保留全部取值，没有 `=` 的键（如 `?prompt`）视为空字符串值
+pub fn parse_query(query: &[u8]) -> Vec<(String, Vec<String>)> {
+    let mut params: Vec<(String, Vec<String>)> = Vec::new();
+    for pair in query.split(|&b| b == b'&').filter(|pair| !pair.is_empty()) {
+        // 只按第一个 `=` 切分，值中可能包含 base64 填充等 `=` 字符
+        let (key, value) = split_once(pair, b'=').unwrap_or((pair, b""));
+        let key = text(key);
+        let value = decode_query_value(value);
+        match params.iter_mut().find(|(name, _)| *name == key) {
+            Some((_, values)) => values.push(value),
+            None => params.push((key, vec![value])),
+        }
+    }
+    params
+}
+
+// 按 application/x-www-form-urlencoded 规则解码参数值，`+` 表示空格
+fn decode_query_value(value: &[u8]) -> String {
+    let value: Vec<u8> = value.iter().map(|&b| if b == b'+' { b' ' } else { b }).collect();
+    decoded_text(&percent_decode(&value))
+}
+
+// 解码百分号转义，永远不会失败：格式错误的转义（如 `abc%ZZ`、结尾的 `%`）原样保留，
+// 解码结果不是合法 UTF-8 时无效字节重新编码为 `%XX`，如 `%FF%FE` 原样保留，而不是丢弃整个取值
+pub fn safe_decode(s: &str) -> String {
+    decoded_text(&percent_decode(s.as_bytes()))
+}
+
+fn percent_decode(input: &[u8]) -> Vec<u8> {
+    let hex = |b: Option<&u8>| b.and_then(|b| (*b as char).to_digit(16));
+    let mut decoded = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        if input[i] == b'%' {
+            if let (Some(high), Some(low)) = (hex(input.get(i + 1)), hex(input.get(i + 2))) {
+                decoded.push((high * 16 + low) as u8);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(input[i]);
+        i += 1;
+    }
+    decoded
+}
+
+fn split_once(bytes: &[u8], delimiter: u8) -> Option<(&[u8], &[u8])> {
+    let index = bytes.iter().position(|&b| b == delimiter)?;
+    Some((&bytes[..index], &bytes[index + 1..]))
+}
+
+fn text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+// 与 text 不同，无效字节不替换为 U+FFFD，而是还原为大写的百分号转义，避免授权码等取值被悄悄改写
+fn decoded_text(bytes: &[u8]) -> String {
+    let mut decoded = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        decoded.push_str(chunk.valid());
+        for byte in chunk.invalid() {
+            decoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    decoded
+}
+
+// 构造完整的 HTTP 响应，显式声明长度并关闭连接，避免浏览器等待更多数据
+pub fn http_response(status: &str, headers: &[(&str, &str)], content_type: &str, body: &str) -> String {
+    let mut response = response_head(status, headers, content_type, body.len());
+    response.push_str(body);
+    response
+}
+
+// 与 http_response 相同，用于图片等二进制响应体
+pub fn http_response_bytes(status: &str, headers: &[(&str, &str)], content_type: &str, body: &[u8]) -> Vec<u8> {
+    let mut response = response_head(status, headers, content_type, body.len()).into_bytes();
+    response.extend_from_slice(body);
+    response
+}
+
+fn response_head(status: &str, headers: &[(&str, &str)], content_type: &str, length: usize) -> String {
+    let mut head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        status, content_type, length
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    head
+}
+
+// 写出响应后刷新并关闭写端，让浏览器确认响应已结束，成功页面中的关闭脚本才能执行
+pub async fn write_response<S>(stream: &mut S, response: impl AsRef<[u8]>)
+where
+    S: AsyncWrite + Unpin,
+{
+    if stream.write_all(response.as_ref()).await.is_ok() {
+        let _ = stream.flush().await;
+    }
+    let _ = stream.shutdown().await;
+}
+
+// 读取恰好 Content-Length 字节的请求体：先取读请求头时已读到的部分，不足时继续读取，之后的数据被忽略
+pub async fn read_body<S>(stream: &mut S, buffer: &[u8], content_length: usize) -> std::io::Result<Vec<u8>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut body = match buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(end) => buffer[end + 4..].to_vec(),
+        None => Vec::new(),
+    };
+    // 超出 Content-Length 的字节（如流水线中的下一个请求）不属于本次请求体
+    body.truncate(content_length);
+    let mut chunk = [0; 1024];
+    
+    while body.len() < content_length {
+        let wanted = (content_length - body.len()).min(chunk.len());
+        let n = stream.read(&mut chunk[..wanted]).await?;
+        if n == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "connection closed before the declared Content-Length was read",
+            ));
+        }
+        body.extend_from_slice(&chunk[..n]);
+    }
+    
+    Ok(body)
+}
+
+// 读取请求头的结果
+pub enum RequestHead {
+    Complete(Vec<u8>),
+    // 超过大小上限仍未读到请求头结束标记
+    TooLarge,
+    // 读到请求头结束标记之前连接已关闭
+    Incomplete,
+}
+
+// 循环读取直到遇到请求头结束标记 `\r\n\r\n`，避免长 state/code 被截断
+// TCP 可能把请求行拆成多个分段送达，未见到结束标记前不会交给解析器
+pub async fn read_request_head<S>(stream: &mut S, max_bytes: usize) -> std::io::Result<RequestHead>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut buffer = Vec::with_capacity(2048);
+    let mut chunk = [0; 1024];
+    
+    loop {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(RequestHead::Incomplete);
+        }
+        // 结束标记可能横跨两次读取，从上次末尾往前 3 个字节开始查找
+        let start = buffer.len().saturating_sub(3);
+        buffer.extend_from_slice(&chunk[..n]);
+        
+        if let Some(end) = buffer[start..].windows(4).position(|w| w == b"\r\n\r\n") {
+            if start + end + 4 > max_bytes {
+                return Ok(RequestHead::TooLarge);
+            }
+            return Ok(RequestHead::Complete(buffer));
+        }
+        if buffer.len() > max_bytes {
+            return Ok(RequestHead::TooLarge);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn invalid_utf8_round_trips_as_percent_escapes() {
+        assert_eq!(safe_decode("%FF%FE"), "%FF%FE");
+        assert_eq!(safe_decode("a%C3%A9%FFb"), "a\u{e9}%FFb");
+        assert_eq!(parse_query(b"code=%ff%fe"), vec![("code".to_string(), vec!["%FF%FE".to_string()])]);
+    }
+    
+    fn query(pairs: &[(&str, &[&str])]) -> Vec<(String, Vec<String>)> {
+        pairs
+            .iter()
+            .map(|(key, values)| (key.to_string(), values.iter().map(|value| value.to_string()).collect()))
+            .collect()
+    }
+    
+    #[test]
+    fn parse_query_handles_edge_cases() {
+        assert!(parse_query(b"").is_empty());
+        assert!(parse_query(b"&&").is_empty());
+        assert_eq!(parse_query(b"code=a&code=b"), query(&[("code", &["a", "b"])]));
+        assert_eq!(parse_query(b"redirect=%2Fhome%3Fx%3D1"), query(&[("redirect", &["/home?x=1"])]));
+        assert_eq!(parse_query(b"key&key2=v"), query(&[("key", &[""]), ("key2", &["v"])]));
+        assert_eq!(parse_query(b"token=a=b"), query(&[("token", &["a=b"])]));
+    }
+    
+    #[test]
+    fn plus_decodes_to_space() {
+        assert_eq!(parse_query(b"error_description=access+denied"), query(&[("error_description", &["access denied"])]));
+        assert_eq!(parse_query(b"q=a%2Bb"), query(&[("q", &["a+b"])]));
+    }
+    
+    #[test]
+    fn value_keeps_embedded_equals() {
+        assert_eq!(parse_query(b"state=abc=def=="), query(&[("state", &["abc=def=="])]));
+    }
+    
+    #[test]
+    fn repeated_keys_keep_all_values() {
+        let request = parse_http_request(b"GET /callback?scope=a&scope=b HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(request.query, query(&[("scope", &["a", "b"])]));
+        assert_eq!(request.query_param("scope"), Some("a"));
+        assert_eq!(request.query_json(), json!({ "scope": ["a", "b"] }));
+    }
+    
+    #[test]
+    fn empty_value_parameters_keep_their_order() {
+        let request = parse_http_request(b"GET /callback?prompt&code=x&a=1 HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(request.query, query(&[("prompt", &[""]), ("code", &["x"]), ("a", &["1"])]));
+        let params = request.query_json();
+        let keys: Vec<&String> = params.as_object().unwrap().keys().collect();
+        assert_eq!(keys, ["prompt", "code", "a"]);
+    }
+    
+    #[test]
+    fn safe_decode_keeps_malformed_escapes() {
+        assert_eq!(safe_decode("abc%ZZ"), "abc%ZZ");
+        assert_eq!(safe_decode("abc%"), "abc%");
+        assert_eq!(safe_decode("abc%4"), "abc%4");
+        assert_eq!(safe_decode("%41bc"), "Abc");
+    }
+    
+    #[test]
+    fn request_line_version_and_method() {
+        let request = parse_http_request(b"GET /callback?code=x\r\n\r\n").unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.query_param("code"), Some("x"));
+        
+        assert_eq!(parse_http_request(b"/callback HTTP/1.1\r\n\r\n").unwrap_err(), ParseError::MalformedRequestLine);
+        assert_eq!(parse_http_request(b"GET\r\n\r\n").unwrap_err(), ParseError::MalformedRequestLine);
+        assert_eq!(
+            parse_http_request(b"GET /callback HTTP/2.0\r\n\r\n").unwrap_err(),
+            ParseError::UnsupportedVersion("HTTP/2.0".to_string())
+        );
+    }
+    
+    #[tokio::test]
+    async fn request_head_split_across_writes() {
+        for (first, second) in [
+            (&b"GET /callback?co"[..], &b"de=x HTTP/1.1\r\nHost: a\r\n\r\n"[..]),
+            (&b"GET /callback?code=x HTTP/1.1\r\n\r"[..], &b"\n"[..]),
+        ] {
+            let (mut client, mut server) = tokio::io::duplex(64);
+            let read = tokio::spawn(async move { read_request_head(&mut server, 1024).await });
+            client.write_all(first).await.unwrap();
+            tokio::task::yield_now().await;
+            client.write_all(second).await.unwrap();
+            
+            let RequestHead::Complete(buffer) = read.await.unwrap().unwrap() else {
+                panic!("request head should be complete");
+            };
+            assert_eq!(parse_http_request(&buffer).unwrap().query_param("code"), Some("x"));
+        }
+    }
+    
+    #[tokio::test]
+    async fn read_body_stops_at_content_length() {
+        let head = b"POST /callback HTTP/1.1\r\nContent-Length: 8\r\n\r\ncode";
+        let (mut client, mut server) = tokio::io::duplex(64);
+        client.write_all(b"=xyzGARBAGE").await.unwrap();
+        assert_eq!(read_body(&mut server, head, 8).await.unwrap(), b"code=xyz");
+    }
+    
+    #[tokio::test]
+    async fn short_body_times_out() {
+        let head = b"POST /callback HTTP/1.1\r\nContent-Length: 100\r\n\r\ncode=xyz";
+        let (_client, mut server) = tokio::io::duplex(64);
+        let read = tokio::time::timeout(std::time::Duration::from_millis(50), read_body(&mut server, head, 100));
+        assert!(read.await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn write_response_sends_everything_then_closes() {
+        let body = "x".repeat(4096);
+        let response = http_response("200 OK", &[], "text/html; charset=utf-8", &body);
+        let (mut client, mut server) = tokio::io::duplex(256);
+        let write = tokio::spawn(async move { write_response(&mut server, response).await });
+        
+        // 服务器关闭写端后 read_to_end 才会返回
+        let mut received = String::new();
+        client.read_to_string(&mut received).await.unwrap();
+        write.await.unwrap();
+        assert!(received.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(received.contains("Content-Length: 4096\r\n"));
+        assert!(received.contains("Connection: close\r\n"));
+        assert!(received.ends_with(&body));
+    }
+    
+    #[test]
+    fn form_post_body_replaces_query() {
+        let raw = b"POST /callback HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded; charset=UTF-8\r\nContent-Length: 18\r\n\r\n";
+        let mut request = parse_http_request(raw).unwrap();
+        assert!(request.is_form_post());
+        assert_eq!(request.content_length(), Some(18));
+        
+        request.set_form_body(b"code=xyz&state=abc");
+        assert_eq!(request.query_param("code"), Some("xyz"));
+        assert_eq!(request.query_param("state"), Some("abc"));
+        assert_eq!(request.raw_query.as_deref(), Some("code=xyz&state=abc"));
+        
+        let json = parse_http_request(b"POST /callback HTTP/1.1\r\nContent-Type: application/json\r\n\r\n").unwrap();
+        assert!(!json.is_form_post());
+    }
+}
@@ -0,0 +1,184 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use reqwest::header::ACCEPT;
+use ring::signature::{RsaPublicKeyComponents, RSA_PKCS1_2048_8192_SHA256};
+use serde_json::Value;
+use tauri::{command, State};
+
+use crate::client::HttpClient;
+use crate::error::OAuthError;
+use crate::{jwt, lock_recover};
+
+// JWKS 的缓存时间
+const JWKS_TTL: Duration = Duration::from_secs(60 * 60);
+
+// 校验 exp 时允许的时钟偏差
+const CLOCK_SKEW_SECS: u64 = 60;
+
+// 按 JWKS 地址缓存的密钥集（地址, 获取时间, 密钥集）
+static JWKS_CACHE: Mutex<Vec<(String, Instant, Value)>> = Mutex::new(Vec::new());
+
+// 用提供商 JWKS 校验 ID Token 的 RS256 签名，并检查 iss、aud 和 exp，全部通过后返回载荷
+// 签名无效、已过期、iss/aud 不符分别返回 invalid_signature、token_expired、invalid_claims 错误
+#[command]
+pub async fn verify_id_token(
+    id_token: String,
+    jwks_url: String,
+    expected_issuer: String,
+    expected_audience: String,
+    client: State<'_, HttpClient>,
+) -> Result<Value, OAuthError> {
+    client.check_trusted(&jwks_url)?;
+    let client = client.get();
+    
+    let token = id_token.trim();
+    let segments: Vec<&str> = token.split('.').collect();
+    if segments.len() != 3 {
+        return Err(OAuthError::InvalidSignature(format!(
+            "malformed JWT: expected 3 segments, found {}",
+            segments.len()
+        )));
+    }
+    let header = decode_segment(segments[0])
+        .and_then(|header| serde_json::from_slice::<Value>(&header).map_err(|e| e.to_string()))
+        .map_err(|e| OAuthError::InvalidSignature(format!("malformed JWT header: {}", e)))?;
+    if header["alg"].as_str() != Some("RS256") {
+        return Err(OAuthError::InvalidSignature(format!(
+            "unsupported JWT algorithm: {}",
+            header["alg"]
+        )));
+    }
+    let kid = header["kid"].as_str();
+    
+    // 缓存中找不到 kid 时重新获取一次，提供商可能已轮换密钥
+    let mut jwks = fetch_jwks(&client, &jwks_url, false).await?;
+    if kid.is_some() && find_keys(&jwks, kid).is_empty() {
+        jwks = fetch_jwks(&client, &jwks_url, true).await?;
+    }
+    let keys = find_keys(&jwks, kid);
+    if keys.is_empty() {
+        return Err(OAuthError::InvalidSignature(format!(
+            "no RSA key in JWKS matches kid {}",
+            kid.unwrap_or("(none)")
+        )));
+    }
+    
+    let message = format!("{}.{}", segments[0], segments[1]);
+    let signature = decode_segment(segments[2])
+        .map_err(|e| OAuthError::InvalidSignature(format!("malformed JWT signature: {}", e)))?;
+    if !keys.iter().any(|key| verify_signature(key, message.as_bytes(), &signature)) {
+        return Err(OAuthError::InvalidSignature("ID token signature verification failed".to_string()));
+    }
+    
+    let claims = jwt::decode_claims(token)?;
+    check_claims(&claims, &expected_issuer, &expected_audience)?;
+    Ok(claims)
+}
+
+fn check_claims(claims: &Value, expected_issuer: &str, expected_audience: &str) -> Result<(), OAuthError> {
+    let exp = claims["exp"]
+        .as_u64()
+        .ok_or_else(|| OAuthError::InvalidClaims("ID token does not include a numeric exp claim".to_string()))?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    if now >= exp + CLOCK_SKEW_SECS {
+        return Err(OAuthError::TokenExpired(format!("ID token expired at {}", exp)));
+    }
+    
+    if claims["iss"].as_str() != Some(expected_issuer) {
+        return Err(OAuthError::InvalidClaims(format!(
+            "issuer mismatch: expected {}, got {}",
+            expected_issuer, claims["iss"]
+        )));
+    }
+    
+    // aud 可以是字符串或字符串数组
+    let audience_matches = match &claims["aud"] {
+        Value::String(aud) => aud == expected_audience,
+        Value::Array(auds) => auds.iter().any(|aud| aud.as_str() == Some(expected_audience)),
+        _ => false,
+    };
+    if !audience_matches {
+        return Err(OAuthError::InvalidClaims(format!(
+            "audience mismatch: expected {}, got {}",
+            expected_audience, claims["aud"]
+        )));
+    }
+    
+    Ok(())
+}
+
+// 读取缓存的密钥集，过期或 refresh 为 true 时重新获取
+async fn fetch_jwks(client: &reqwest::Client, jwks_url: &str, refresh: bool) -> Result<Value, OAuthError> {
+    if !refresh {
+        let cache = lock_recover(&JWKS_CACHE);
+        let cached = cache
+            .iter()
+            .find(|(url, fetched_at, _)| url == jwks_url && fetched_at.elapsed() < JWKS_TTL);
+        if let Some((_, _, jwks)) = cached {
+            return Ok(jwks.clone());
+        }
+    }
+    
+    let response = client
+        .get(jwks_url)
+        .header(ACCEPT, "application/json")
+        .send()
+        .await
+        .map_err(|e| OAuthError::Network(format!("JWKS request failed: {}", e)))?;
+    let status = response.status();
+    let body = response
+        .text()
+        .await
+        .map_err(|e| OAuthError::Network(format!("failed to read JWKS response: {}", e)))?;
+    if !status.is_success() {
+        return Err(OAuthError::Provider {
+            status: Some(status.as_u16()),
+            body,
+        });
+    }
+    let jwks: Value = serde_json::from_str(&body).map_err(|e| format!("invalid JWKS response: {}", e))?;
+    
+    let mut cache = lock_recover(&JWKS_CACHE);
+    cache.retain(|(url, _, _)| url != jwks_url);
+    cache.push((jwks_url.to_string(), Instant::now(), jwks.clone()));
+    Ok(jwks)
+}
+
+// 可用于校验的 RSA 密钥；ID Token 没有 kid 时尝试全部 RSA 密钥
+fn find_keys<'a>(jwks: &'a Value, kid: Option<&str>) -> Vec<&'a Value> {
+    jwks["keys"]
+        .as_array()
+        .map(|keys| {
+            keys.iter()
+                .filter(|key| key["kty"].as_str() == Some("RSA"))
+                .filter(|key| key["use"].as_str().is_none_or(|usage| usage == "sig"))
+                .filter(|key| kid.is_none() || key["kid"].as_str() == kid)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn verify_signature(key: &Value, message: &[u8], signature: &[u8]) -> bool {
+    let (Some(n), Some(e)) = (key["n"].as_str(), key["e"].as_str()) else {
+        return false;
+    };
+    let (Ok(n), Ok(e)) = (decode_segment(n), decode_segment(e)) else {
+        return false;
+    };
+    RsaPublicKeyComponents { n: &n, e: &e }
+        .verify(&RSA_PKCS1_2048_8192_SHA256, message, signature)
+        .is_ok()
+}
+
+// 部分实现会保留 base64 填充，解码前去掉
+fn decode_segment(segment: &str) -> Result<Vec<u8>, String> {
+    URL_SAFE_NO_PAD
+        .decode(segment.trim_end_matches('='))
+        .map_err(|e| e.to_string())
+}
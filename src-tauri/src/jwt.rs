@@ -0,0 +1,74 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde_json::Value;
+use tauri::command;
+
+use crate::error::OAuthError;
+
+// 判断过期时预留的时钟偏差，令牌在到期前 60 秒即视为已过期
+const EXPIRY_SKEW_SECS: u64 = 60;
+
+// 超过该值的 expires_at 视为毫秒时间戳（前端 Date.now() 的结果）
+const MILLIS_THRESHOLD: u64 = 100_000_000_000;
+
+// 解码 OpenID Connect ID Token 的载荷，不校验签名
+// 返回值中带有 `_verified: false` 标记，调用方不应将其视为可信身份
+#[command]
+pub fn decode_id_token(id_token: String) -> Result<Value, OAuthError> {
+    let mut claims = decode_claims(&id_token)?;
+    if let Some(object) = claims.as_object_mut() {
+        object.insert("_verified".to_string(), Value::Bool(false));
+    }
+    
+    Ok(claims)
+}
+
+// 拆分 JWT 并解码载荷段为 JSON 对象
+pub fn decode_claims(token: &str) -> Result<Value, String> {
+    let segments: Vec<&str> = token.trim().split('.').collect();
+    if segments.len() != 3 {
+        return Err(format!(
+            "malformed JWT: expected 3 segments, found {}",
+            segments.len()
+        ));
+    }
+    
+    // 部分实现会保留 base64 填充，解码前去掉
+    let payload = URL_SAFE_NO_PAD
+        .decode(segments[1].trim_end_matches('='))
+        .map_err(|e| format!("malformed JWT payload: {}", e))?;
+    
+    let claims: Value = serde_json::from_slice(&payload)
+        .map_err(|e| format!("JWT payload is not valid JSON: {}", e))?;
+    if !claims.is_object() {
+        return Err("JWT payload is not a JSON object".to_string());
+    }
+    
+    Ok(claims)
+}
+
+// 判断令牌是否已过期（含 60 秒偏差），参数可以是 ID Token / JWT 访问令牌，也可以是保存的 expires_at
+// （Unix 秒或毫秒时间戳），无法解析时返回错误而不是假定有效
+#[command]
+pub fn token_is_expired(id_token_or_expires_at: String) -> Result<bool, OAuthError> {
+    Ok(is_expired(&id_token_or_expires_at)?)
+}
+
+pub fn is_expired(id_token_or_expires_at: &str) -> Result<bool, String> {
+    let input = id_token_or_expires_at.trim();
+    let expires_at = match input.parse::<u64>() {
+        Ok(millis) if millis >= MILLIS_THRESHOLD => millis / 1000,
+        Ok(secs) => secs,
+        Err(_) => decode_claims(input)?["exp"]
+            .as_u64()
+            .ok_or("JWT does not include a numeric exp claim")?,
+    };
+    
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    Ok(now + EXPIRY_SKEW_SECS >= expires_at)
+}
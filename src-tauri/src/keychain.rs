@@ -0,0 +1,141 @@
+use keyring::Entry;
+use serde_json::Value;
+use tauri::command;
+
+use crate::error::OAuthError;
+
+// 钥匙串中的服务名，各提供商的令牌以提供商名作为账户区分
+const KEYCHAIN_SERVICE: &str = "com.blog.app.oauth";
+
+// 记录保存过令牌的提供商列表的账户名；钥匙串无法按服务枚举账户，全部清除时依赖该列表
+const PROVIDER_INDEX_ACCOUNT: &str = "__providers__";
+
+// 将令牌保存到系统密钥存储，避免刷新令牌留在 webview 的 localStorage 中
+#[command]
+pub async fn save_oauth_tokens(provider: String, tokens: Value) -> Result<(), OAuthError> {
+    Ok(save_tokens(&provider, &tokens)?)
+}
+
+#[command]
+pub async fn load_oauth_tokens(provider: String) -> Result<Option<Value>, OAuthError> {
+    Ok(load_tokens(&provider)?)
+}
+
+#[command]
+pub async fn delete_oauth_tokens(provider: String) -> Result<(), OAuthError> {
+    Ok(delete_tokens(&provider)?)
+}
+
+pub fn save_tokens(provider: &str, tokens: &Value) -> Result<(), String> {
+    let secret = serde_json::to_string(tokens).map_err(|e| format!("failed to serialize tokens: {}", e))?;
+    entry(provider)?
+        .set_password(&secret)
+        .map_err(|e| keychain_error("save", provider, e))?;
+    
+    let mut providers = load_provider_index()?;
+    if !providers.iter().any(|name| name == provider.trim()) {
+        providers.push(provider.trim().to_string());
+        save_provider_index(&providers)?;
+    }
+    Ok(())
+}
+
+pub fn load_tokens(provider: &str) -> Result<Option<Value>, String> {
+    match entry(provider)?.get_password() {
+        Ok(secret) => serde_json::from_str(&secret)
+            .map(Some)
+            .map_err(|e| format!("stored tokens for {} are corrupted: {}", provider, e)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(keychain_error("load", provider, e)),
+    }
+}
+
+pub fn delete_tokens(provider: &str) -> Result<(), String> {
+    match entry(provider)?.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => {}
+        Err(e) => return Err(keychain_error("delete", provider, e)),
+    }
+    
+    let mut providers = load_provider_index()?;
+    let count = providers.len();
+    providers.retain(|name| name != provider.trim());
+    if providers.len() != count {
+        save_provider_index(&providers)?;
+    }
+    Ok(())
+}
+
+// 删除全部保存过的令牌，返回被删除令牌的提供商
+pub fn delete_all_tokens() -> Result<Vec<String>, String> {
+    let providers = load_provider_index()?;
+    for provider in &providers {
+        delete_tokens(provider)?;
+    }
+    Ok(providers)
+}
+
+fn load_provider_index() -> Result<Vec<String>, String> {
+    match index_entry()?.get_password() {
+        Ok(secret) => serde_json::from_str(&secret).map_err(|e| format!("provider index is corrupted: {}", e)),
+        Err(keyring::Error::NoEntry) => Ok(Vec::new()),
+        Err(e) => Err(keychain_error("load", PROVIDER_INDEX_ACCOUNT, e)),
+    }
+}
+
+fn save_provider_index(providers: &[String]) -> Result<(), String> {
+    let entry = index_entry()?;
+    if providers.is_empty() {
+        return match entry.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(keychain_error("delete", PROVIDER_INDEX_ACCOUNT, e)),
+        };
+    }
+    let secret = serde_json::to_string(providers).map_err(|e| format!("failed to serialize provider index: {}", e))?;
+    entry
+        .set_password(&secret)
+        .map_err(|e| keychain_error("save", PROVIDER_INDEX_ACCOUNT, e))
+}
+
+// 提供商的令牌条目；提供商列表占用的账户名保留，否则保存该名称的令牌会覆盖列表
+fn entry(provider: &str) -> Result<Entry, String> {
+    let provider = provider.trim();
+    if provider.is_empty() {
+        return Err("provider must not be empty".to_string());
+    }
+    if provider == PROVIDER_INDEX_ACCOUNT {
+        return Err(format!("provider name {} is reserved", PROVIDER_INDEX_ACCOUNT));
+    }
+    
+    Entry::new(KEYCHAIN_SERVICE, provider).map_err(|e| keychain_error("open", provider, e))
+}
+
+fn index_entry() -> Result<Entry, String> {
+    Entry::new(KEYCHAIN_SERVICE, PROVIDER_INDEX_ACCOUNT).map_err(|e| keychain_error("open", PROVIDER_INDEX_ACCOUNT, e))
+}
+
+// 区分钥匙串被锁定/不可用与其他错误，方便前端提示
+fn keychain_error(action: &str, provider: &str, e: keyring::Error) -> String {
+    match e {
+        keyring::Error::NoStorageAccess(err) => {
+            format!("keychain is locked or inaccessible ({} {}): {}", action, provider, err)
+        }
+        keyring::Error::PlatformFailure(err) => {
+            format!("keychain is unavailable ({} {}): {}", action, provider, err)
+        }
+        e => format!("failed to {} tokens for {}: {}", action, provider, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn provider_index_account_is_reserved() {
+        for provider in [PROVIDER_INDEX_ACCOUNT, " __providers__ "] {
+            let error = entry(provider).expect_err("reserved account must be rejected");
+            assert!(error.contains("reserved"), "{}", error);
+        }
+        assert!(save_tokens(PROVIDER_INDEX_ACCOUNT, &Value::Null).is_err());
+    }
+}
@@ -1,118 +1,1997 @@
-use std::sync::{Arc, Mutex};
-use std::collections::HashMap;
-use tauri::{command, State, Emitter};
-use tokio::net::TcpListener;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use tauri::{command, State, Manager};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
 use serde_json::json;
-use urlencoding;
+use tokio::sync::{mpsc, oneshot, Semaphore};
+use tokio_rustls::TlsAcceptor;
+use security::SecurityConfig;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::EnvFilter;
+use error::OAuthError;
+use http::{http_response, parse_http_request, read_body, read_request_head, safe_decode, write_response, HttpRequest, RequestHead};
+
+mod api;
+mod assets;
+mod client;
+mod device;
+mod error;
+mod flows;
+mod http;
+mod jwks;
+mod jwt;
+mod keychain;
+mod login;
+#[cfg(feature = "test-provider")]
+pub mod mock_provider;
+mod pending;
+mod pkce;
+mod presets;
+mod private_file;
+mod security;
+mod sink;
+mod tls;
+mod token;
+mod userinfo;
+#[cfg(test)]
+mod test_support;
+
+// 发送给前端的事件：
+// - `oauth-server-ready`：回调服务器已开始监听，载荷为 { flow_id, port }
+// - `oauth-callback`：收到成功的 OAuth 回调，载荷为 { flow_id, provider, code, state, raw_params, peer, received_at, raw_path }
+// - `oauth-callback-error`：提供商返回错误或回调校验失败，载荷为 { flow_id, provider, error, error_description, state, raw_params, peer, received_at, raw_path }
+// - `oauth-server-error`：回调服务器绑定失败或持续无法接受连接，载荷为 { flow_id, port, message }；
+//   diagnose 自检（请求 GET /healthz）失败时额外带有 code: "loopback_blocked"
+// - `oauth-server-stopped`：回调服务器已停止并释放端口，载荷为 { flow_id, port }
+// - `oauth-flow-timeout`：在 flow_timeout_secs 内未收到成功的回调，服务器随后停止，载荷为 { flow_id, port }
+// - `oauth-device-code`：设备授权流程已开始，载荷为 { device_code, user_code, verification_uri, verification_uri_complete, expires_in, interval }
+// - `oauth-device-pending`：设备授权轮询中用户尚未完成授权，载荷为 { device_code, attempt, interval }
+// - `oauth-progress`：oauth_login 进入新的阶段，载荷为 { flow_id, stage }，stage 依次为
+//   server_started、browser_opened、callback_received、exchanging、fetching_userinfo（仅配置了 userinfo_url 时）和 done
+// 通过 capture_fragment 从 URL 片段取回的 oauth-callback 载荷以 { access_token, id_token, token_type, expires_in, fragment: true } 代替 code
+// flow_id 为启动服务器时传入的流程标识，便于前端区分并发的登录流程
+// raw_params 为全部查询参数，键按首次出现的顺序排列，每个键对应按出现顺序排列的取值列表，没有值的键取值为空字符串
+// peer 为发起回调的客户端地址，received_at 为收到回调的 Unix 毫秒时间戳，raw_path 为解码后的完整请求路径，便于调试
 
 // OAuth 服务器状态
-type OAuthServerState = Arc<Mutex<HashMap<u16, tokio::task::JoinHandle<()>>>>;
+#[derive(Default)]
+struct OAuthServerState {
+    servers: ServerTable,
+    // 未能送达前端的最近一次回调载荷，前端启动后通过 take_pending_oauth_callback 取回；应用退出时写入磁盘
+    pending_callback: Mutex<Option<serde_json::Value>>,
+    // 进行中的 oauth_login，按 flow_id 保存取消令牌
+    logins: Mutex<HashMap<String, CancellationToken>>,
+    // 同时运行的服务器数量上限，未设置时为 DEFAULT_MAX_SERVERS
+    max_servers: Mutex<Option<usize>>,
+    // 最近发出的回调事件载荷，最新的在前，最多保留 EVENT_HISTORY_LIMIT 条
+    event_history: Mutex<VecDeque<serde_json::Value>>,
+}
+
+// 按端口登记的服务器；服务器任务持有一份引用，自动停止时自行移除
+type ServerTable = Arc<Mutex<HashMap<u16, ServerHandle>>>;
+
+// 回调事件历史保留的条数
+const EVENT_HISTORY_LIMIT: usize = 20;
+
+// 默认允许同时运行的服务器数量，防止前端异常循环启动服务器耗尽文件描述符
+const DEFAULT_MAX_SERVERS: usize = 8;
+
+// 获取锁；持锁线程 panic 导致锁中毒时恢复其中的数据继续使用，
+// 避免一次意外 panic 让本次会话的所有 OAuth 命令都失效
+fn lock_recover<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| {
+        warn!("Recovering from a poisoned OAuth state lock");
+        poisoned.into_inner()
+    })
+}
+
+// 停止服务器时等待进行中的连接写完响应的最长时间
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(2);
+
+// 单个回调服务器的运行句柄
+struct ServerHandle {
+    task: tokio::task::JoinHandle<()>,
+    context: Arc<ServerContext>,
+}
+
+// 回调结果：成功时为 oauth-callback 的载荷，失败时为 oauth-callback-error 的载荷
+type CallbackOutcome = Result<serde_json::Value, serde_json::Value>;
+
+// 连接处理方向发送任务提交回调事件的队列，事件发送完成（或暂存）后通过 oneshot 通知提交方
+type EmitQueue = mpsc::Sender<(&'static str, serde_json::Value, oneshot::Sender<()>)>;
+
+// 服务器任务与状态表共享的运行信息
+struct ServerContext {
+    flow_id: Option<String>,
+    options: ServerOptions,
+    security: SecurityConfig,
+    tls: Option<TlsAcceptor>,
+    shutdown: CancellationToken,
+    started_at: Instant,
+    alive: AtomicBool,
+    // 实际监听的本地地址（IPv4 以及可选的 IPv6 回环地址），绑定成功后写入
+    local_addrs: Mutex<Vec<SocketAddr>>,
+    callbacks_received: AtomicU64,
+    // 正在处理的连接数，达到 security.max_connections 时服务器已饱和
+    in_flight: AtomicUsize,
+    // 已发出的 oauth-callback-error 和 oauth-server-error 事件数
+    errors_emitted: AtomicU64,
+    // new_oauth_state 为该服务器生成的 state，设置后优先于 options.expected_state
+    issued_state: Mutex<Option<String>>,
+    // 收到成功的回调后取消，用于结束流程超时计时
+    flow_completed: CancellationToken,
+    // Rust 侧等待回调的一方（如 oauth_login），收到第一个回调后通知它
+    callback_waiter: Mutex<Option<oneshot::Sender<CallbackOutcome>>>,
+    // 最近一次回调查询串的哈希及时间，用于过滤浏览器重复发起的回调
+    last_callback: Mutex<Option<(u64, Instant)>>,
+    // 按来源 IP 记录的令牌桶（剩余令牌数, 上次补充时间），限制回调频率
+    rate_limits: Mutex<HashMap<IpAddr, (f64, Instant)>>,
+}
+
+impl ServerContext {
+    // 判断是否为短时间内重复到达的同一回调，并记录本次回调
+    fn is_duplicate_callback(&self, query: &str) -> bool {
+        let mut hasher = DefaultHasher::new();
+        query.hash(&mut hasher);
+        let hash = hasher.finish();
+        
+        let mut last = lock_recover(&self.last_callback);
+        let duplicate = matches!(
+            *last,
+            Some((last_hash, seen_at)) if last_hash == hash && seen_at.elapsed() < DUPLICATE_CALLBACK_WINDOW
+        );
+        *last = Some((hash, Instant::now()));
+        duplicate
+    }
+    
+    // 令牌桶限流；来源端口每个连接都不同，因此按 IP 计数
+    fn allow_callback(&self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        let mut buckets = lock_recover(&self.rate_limits);
+        let burst = self.security.rate_limit_burst;
+        let (tokens, refilled_at) = buckets.entry(ip).or_insert((burst, now));
+        
+        let elapsed = now.duration_since(*refilled_at).as_secs_f64();
+        *tokens = (*tokens + elapsed * self.security.rate_limit_per_sec).min(burst);
+        *refilled_at = now;
+        
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+    
+    fn new(
+        flow_id: Option<String>,
+        options: ServerOptions,
+        security: SecurityConfig,
+        tls: Option<TlsAcceptor>,
+    ) -> Self {
+        Self {
+            flow_id,
+            options,
+            security,
+            tls,
+            shutdown: CancellationToken::new(),
+            started_at: Instant::now(),
+            alive: AtomicBool::new(false),
+            local_addrs: Mutex::new(Vec::new()),
+            callbacks_received: AtomicU64::new(0),
+            in_flight: AtomicUsize::new(0),
+            errors_emitted: AtomicU64::new(0),
+            issued_state: Mutex::new(None),
+            flow_completed: CancellationToken::new(),
+            callback_waiter: Mutex::new(None),
+            last_callback: Mutex::new(None),
+            rate_limits: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+// oauth_server_status 返回的服务器状态
+#[derive(Debug, Serialize)]
+struct ServerStatus {
+    listening: bool,
+    local_addrs: Vec<SocketAddr>,
+    callbacks_received: u64,
+    errors_emitted: u64,
+    uptime_secs: u64,
+    in_flight_connections: usize,
+    max_connections: usize,
+}
+
+// oauth_metrics 返回的所有服务器的汇总
+#[derive(Debug, Serialize)]
+struct Metrics {
+    total_servers: usize,
+    total_callbacks: u64,
+    total_errors: u64,
+    // 运行时间最长的服务器已运行的秒数，没有服务器时为 null
+    oldest_uptime_secs: Option<u64>,
+}
+
+impl ServerHandle {
+    fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+    
+    fn status(&self) -> ServerStatus {
+        ServerStatus {
+            listening: !self.is_finished() && self.context.alive.load(Ordering::Relaxed),
+            local_addrs: lock_recover(&self.context.local_addrs).clone(),
+            callbacks_received: self.context.callbacks_received.load(Ordering::Relaxed),
+            errors_emitted: self.context.errors_emitted.load(Ordering::Relaxed),
+            uptime_secs: self.context.started_at.elapsed().as_secs(),
+            in_flight_connections: self.context.in_flight.load(Ordering::Relaxed),
+            max_connections: self.context.security.max_connections,
+        }
+    }
+    
+    // 通知监听循环退出并等待其处理完进行中的连接，超时后强制中止
+    async fn stop(mut self) {
+        self.context.shutdown.cancel();
+        if tokio::time::timeout(SHUTDOWN_GRACE_PERIOD, &mut self.task).await.is_err() {
+            self.task.abort();
+            let _ = self.task.await;
+        }
+    }
+}
+
+// 默认的回调路径前缀
+const DEFAULT_CALLBACK_PATH: &str = "/callback/";
+
+// 回调事件发送失败后默认的重试次数及每次重试前的等待时间
+const DEFAULT_EMIT_RETRIES: u32 = 1;
+const DEFAULT_EMIT_RETRY_INTERVAL_MS: u64 = 500;
+
+// 重试发送回调事件的总等待时间上限，期间浏览器保持加载状态
+const MAX_EMIT_WAIT: Duration = Duration::from_secs(5);
+
+// 该时间窗口内相同的回调视为浏览器预取或重试，只通知一次
+const DUPLICATE_CALLBACK_WINDOW: Duration = Duration::from_secs(5);
+
+// accept 连续出错时的退避时间范围
+const ACCEPT_BACKOFF_INITIAL: Duration = Duration::from_millis(50);
+const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(5);
+
+// accept 连续失败达到该次数时通知前端
+const ACCEPT_FAILURES_BEFORE_REPORT: u32 = 5;
+
+// 回环自检等待连接成功的时间
+const LOOPBACK_DIAGNOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+// start_oauth_server 的可选配置，未提供的字段使用默认值
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+struct ServerOptions {
+    // 发起授权时生成的 state，设置后回调中的 state 必须与之一致
+    expected_state: Option<String>,
+    // 自定义回调成功页面，便于前端按当前语言传入品牌化页面
+    success_html: Option<String>,
+    // 拿到授权码后以 302 重定向到该地址（如托管的登录完成页面），代替内置的成功页面
+    success_redirect: Option<String>,
+    // 品牌化静态资源目录，设置后 /static/ 下的请求返回该目录中的文件，成功页面可以引用它们
+    success_asset_dir: Option<PathBuf>,
+    // 浏览器不会把 URL 片段（#error=...、#access_token=...）发给服务器；开启后成功页面通过脚本
+    // 把片段中的参数 POST 回 /fragment 加回调路径，服务器再以 oauth-callback 事件通知前端
+    capture_fragment: bool,
+    // 启动后连接一次自身端口，失败时发送带 loopback_blocked 代码的 oauth-server-error，
+    // 用于发现绑定成功但回环连接被安全软件拦截、回调只会一直挂起的情况
+    diagnose: bool,
+    // 以 info 级别记录每个回调，code、state 和令牌只保留首尾几个字符，error / error_description 完整记录，
+    // 便于用户把日志贴到 issue 中；默认关闭
+    log_callbacks: bool,
+    // 回调事件发送失败（如 webview 正在重载）时的重试次数和间隔（毫秒），默认 1 次、500 毫秒
+    // 重试期间回调页面的响应保持挂起，总等待时间不超过 5 秒
+    emit_retries: Option<u32>,
+    emit_retry_interval_ms: Option<u64>,
+    // 收到第一个成功的回调后自动停止服务器，默认开启
+    auto_stop: Option<bool>,
+    // 监听地址，默认同时监听 127.0.0.1 和 ::1；可以指定 127.0.0.2 等回环别名，
+    // 供浏览器运行在容器或虚拟机中的环境使用，非回环地址需要 allow_external
+    host: Option<String>,
+    // 是否允许监听非回环地址，暴露到局域网存在安全风险，默认关闭
+    allow_external: bool,
+    // 回调路径前缀，默认 /callback/，其后的一段路径作为提供商名称
+    callback_path: Option<String>,
+    // 允许的提供商名称，设置后回调路径中的提供商必须在列表中，否则返回 unknown_provider 错误
+    allowed_providers: Option<Vec<String>>,
+    // 允许的完整回调路径（如 /callback/github），设置后路径不完全一致的回调返回 400 和 invalid_redirect 错误
+    // 为空时接受回调路径前缀下的任意路径
+    allowed_paths: Vec<String>,
+    // 在该时间（秒）内未收到成功的回调时自动停止服务器并发送 oauth-flow-timeout
+    flow_timeout_secs: Option<u64>,
+    // 使用自签名证书提供 https 回调，供要求 https 回调地址的提供商使用
+    // 提供商需要接受自签名证书，或由用户在浏览器中确认一次安全警告
+    use_tls: bool,
+}
+
+impl ServerOptions {
+    // 解析监听地址，非回环地址必须显式允许
+    fn bind_ip(&self) -> Result<IpAddr, String> {
+        let ip = match &self.host {
+            Some(host) => host
+                .trim()
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse::<IpAddr>()
+                .map_err(|e| format!("invalid host {}: {}", host, e))?,
+            None => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        };
+        
+        if !ip.is_loopback() && !self.allow_external {
+            return Err(format!(
+                "refusing to bind non-loopback address {} without allow_external",
+                ip
+            ));
+        }
+        
+        Ok(ip)
+    }
+    
+    fn callback_path(&self) -> Result<&str, String> {
+        let path = self.callback_path.as_deref().unwrap_or(DEFAULT_CALLBACK_PATH);
+        if !path.starts_with('/') {
+            return Err(format!("callback_path must start with '/': {}", path));
+        }
+        Ok(path)
+    }
+    
+    // 重定向地址必须是绝对的 http(s) 地址，返回规范化后的地址用于 Location 响应头
+    // Url::parse 会静默去掉 CR、LF 和制表符，含控制字符的值必须先拒绝，否则可以借此注入响应头
+    fn success_redirect(&self) -> Result<Option<String>, String> {
+        let Some(redirect) = self.success_redirect.as_deref() else {
+            return Ok(None);
+        };
+        if redirect.chars().any(char::is_control) {
+            return Err(format!("success_redirect must not contain control characters: {:?}", redirect));
+        }
+        let url = url::Url::parse(redirect).map_err(|e| format!("invalid success_redirect {}: {}", redirect, e))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("success_redirect must use http or https: {}", redirect));
+        }
+        Ok(Some(url.into()))
+    }
+    
+    fn emit_retry(&self) -> Result<(u32, Duration), String> {
+        let retries = self.emit_retries.unwrap_or(DEFAULT_EMIT_RETRIES);
+        let interval = Duration::from_millis(self.emit_retry_interval_ms.unwrap_or(DEFAULT_EMIT_RETRY_INTERVAL_MS));
+        if interval.saturating_mul(retries) > MAX_EMIT_WAIT {
+            return Err(format!(
+                "emit_retries * emit_retry_interval_ms must not exceed {}ms",
+                MAX_EMIT_WAIT.as_millis()
+            ));
+        }
+        Ok((retries, interval))
+    }
+    
+    fn success_asset_dir(&self) -> Result<Option<&Path>, String> {
+        let Some(dir) = self.success_asset_dir.as_deref() else {
+            return Ok(None);
+        };
+        if !dir.is_dir() {
+            return Err(format!("success_asset_dir is not a directory: {}", dir.display()));
+        }
+        Ok(Some(dir))
+    }
+    
+    // 与监听地址、端口、TLS 和回调路径一致的回调地址，提供商名称需追加在末尾
+    // 监听所有地址时使用 127.0.0.1
+    fn redirect_uri(&self, port: u16) -> String {
+        let scheme = if self.use_tls { "https" } else { "http" };
+        let host = match self.bind_ip() {
+            Ok(IpAddr::V6(ip)) if !ip.is_unspecified() => format!("[{}]", ip),
+            Ok(IpAddr::V4(ip)) if !ip.is_unspecified() => ip.to_string(),
+            _ => Ipv4Addr::LOCALHOST.to_string(),
+        };
+        let path = self.callback_path().unwrap_or(DEFAULT_CALLBACK_PATH);
+        format!("{}://{}:{}{}", scheme, host, port, path)
+    }
+    
+    // 匹配回调路径，匹配成功时返回路径中携带的提供商（可能没有）
+    fn match_callback<'a>(&self, route: &'a str) -> Option<Option<&'a str>> {
+        let base = self.callback_path().ok()?.trim_end_matches('/');
+        if route == base {
+            return Some(None);
+        }
+        
+        let rest = route.strip_prefix(base)?.strip_prefix('/')?;
+        Some(rest.split('/').next().filter(|segment| !segment.is_empty()))
+    }
+    
+    // 未设置允许列表时接受任意提供商
+    fn allows_provider(&self, provider: &str) -> bool {
+        match &self.allowed_providers {
+            Some(allowed) => allowed.iter().any(|name| name.trim().eq_ignore_ascii_case(provider)),
+            None => true,
+        }
+    }
+    
+    fn allows_path(&self, path: &str) -> bool {
+        self.allowed_paths.is_empty() || self.allowed_paths.iter().any(|allowed| allowed == path)
+    }
+    
+    fn auto_stop(&self) -> bool {
+        self.auto_stop.unwrap_or(true)
+    }
+    
+    // 回调成功页面，自定义页面末尾追加关闭窗口的脚本；开启 capture_fragment 时在关闭前先回传 URL 片段
+    fn success_html(&self) -> String {
+        let scripts = match self.capture_fragment {
+            true => format!("{}{}", FRAGMENT_CAPTURE_SCRIPT, CLOSE_WINDOW_SCRIPT),
+            false => CLOSE_WINDOW_SCRIPT.to_string(),
+        };
+        match &self.success_html {
+            Some(html) => format!("{}{}", html, scripts),
+            None => DEFAULT_SUCCESS_HTML.replace(CLOSE_WINDOW_SCRIPT, &scripts),
+        }
+    }
+    
+    // 脚本回传的 URL 片段请求，返回其中的回调路径
+    fn match_fragment<'a>(&self, request: &'a HttpRequest) -> Option<&'a str> {
+        if !self.capture_fragment || !request.is_form_post() {
+            return None;
+        }
+        request.path.strip_prefix(FRAGMENT_PATH).filter(|path| path.starts_with('/'))
+    }
+}
+
+// start_oauth_server 的返回值，前端应直接使用 redirect_uri 构造授权地址
+#[derive(Debug, Serialize)]
+struct StartResult {
+    port: u16,
+    redirect_uri: String,
+}
 
 #[command]
 async fn start_oauth_server(
     port: u16,
+    flow_id: Option<String>,
+    options: Option<ServerOptions>,
+    security: Option<SecurityConfig>,
+    force: Option<bool>,
     state: State<'_, OAuthServerState>,
     app: tauri::AppHandle,
-) -> Result<(), String> {
-    let mut servers = state.lock().map_err(|e| e.to_string())?;
+) -> Result<StartResult, OAuthError> {
+    let options = options.unwrap_or_default();
+    let security = security.unwrap_or_default();
+    launch_server(port, flow_id, options, security, force.unwrap_or(false), &state, Arc::new(app)).await
+}
+
+// 重启指定端口的服务器，沿用其原有配置，重新监听后才返回
+#[command]
+async fn restart_oauth_server(
+    port: u16,
+    state: State<'_, OAuthServerState>,
+    app: tauri::AppHandle,
+) -> Result<StartResult, OAuthError> {
+    restart_server(port, &state, Arc::new(app)).await
+}
+
+// new_oauth_state 登记的 state 同样沿用，否则重启后的服务器不再校验 state
+async fn restart_server(
+    port: u16,
+    state: &OAuthServerState,
+    sink: Arc<dyn sink::CallbackSink>,
+) -> Result<StartResult, OAuthError> {
+    let (flow_id, options, security, issued_state) = lock_recover(&state.servers)
+        .get(&port)
+        .map(|handle| {
+            let context = &handle.context;
+            let issued_state = lock_recover(&context.issued_state).clone();
+            (context.flow_id.clone(), context.options.clone(), context.security.clone(), issued_state)
+        })
+        .ok_or_else(|| OAuthError::NotFound(format!("no server registered on port {}", port)))?;
+    
+    start_server_task(port, flow_id, options, security, true, issued_state, state, sink).await
+}
+
+// 启动新服务器并登记到状态中
+// 端口上已有 flow_id 和配置都相同且仍在监听的服务器时直接复用，避免 webview 重载时反复重新绑定；
+// flow_id 或配置不同时只有 force 为 true 才会停止并替换它，否则事件会带上旧的 flow_id；已退出的服务器总是被替换
+// 服务器的全部事件都通过 sink 发出，应用中传入 AppHandle
+async fn launch_server(
+    port: u16,
+    flow_id: Option<String>,
+    options: ServerOptions,
+    security: SecurityConfig,
+    force: bool,
+    state: &OAuthServerState,
+    sink: Arc<dyn sink::CallbackSink>,
+) -> Result<StartResult, OAuthError> {
+    start_server_task(port, flow_id, options, security, force, None, state, sink).await
+}
+
+// 与 launch_server 相同，issued_state 为新服务器预先登记的 state，用于重启时沿用
+#[allow(clippy::too_many_arguments)]
+async fn start_server_task(
+    port: u16,
+    flow_id: Option<String>,
+    options: ServerOptions,
+    security: SecurityConfig,
+    force: bool,
+    issued_state: Option<String>,
+    state: &OAuthServerState,
+    sink: Arc<dyn sink::CallbackSink>,
+) -> Result<StartResult, OAuthError> {
+    // 先校验配置，避免无效配置导致已有服务器被停止
+    let addr = SocketAddr::new(options.bind_ip()?, port);
+    options.callback_path()?;
+    options.success_redirect()?;
+    options.success_asset_dir()?;
+    options.emit_retry()?;
+    security.validate()?;
+    let tls = match options.use_tls {
+        true => Some(tls::self_signed_acceptor()?),
+        false => None,
+    };
     
-    // 如果服务器已经在运行，先停止它
-    if let Some(handle) = servers.remove(&port) {
-        handle.abort();
+    // 如果服务器已经在运行，先停止它，并等待旧的监听器释放端口
+    let previous = {
+        let mut servers = lock_recover(&state.servers);
+        if let Some(handle) = servers.get(&port).filter(|handle| !force && !handle.is_finished()) {
+            let context = &handle.context;
+            let same_config = context.flow_id == flow_id && context.options == options && context.security == security;
+            if handle.status().listening && same_config {
+                return Ok(StartResult {
+                    port,
+                    redirect_uri: options.redirect_uri(port),
+                });
+            }
+            return Err(OAuthError::PortInUse(format!("server already running on port {}", port)));
+        }
+        
+        check_capacity(&servers, port, state)?;
+        servers.remove(&port)
+    };
+    if let Some(handle) = previous {
+        handle.stop().await;
     }
     
-    let app_clone = app.clone();
-    let handle = tokio::spawn(async move {
-        if let Err(e) = run_oauth_server(port, app_clone).await {
-            eprintln!("OAuth server error: {}", e);
+    // 先绑定端口，再在同一次加锁中启动服务器任务并登记，两步之间没有 await：
+    // 调用方（如被取消的 oauth_login）无论在哪一步放弃本次调用，都不会留下未登记、无法停止的服务器
+    let context = Arc::new(ServerContext::new(flow_id, options, security, tls));
+    *lock_recover(&context.issued_state) = issued_state;
+    let (listener, ipv6_listener) = match bind_listeners(addr, &context).await {
+        Ok(listeners) => listeners,
+        Err(e) => {
+            warn!(port, error = %e, "OAuth callback server failed to bind");
+            emit_server_error(sink.as_ref(), port, context.flow_id.as_deref(), &e.to_string());
+            return Err(e);
         }
-    });
+    };
+    // 传入端口 0 时由系统分配可用端口
+    let port = lock_recover(&context.local_addrs)[0].port();
+    let redirect_uri = context.options.redirect_uri(port);
     
-    servers.insert(port, handle);
+    // 绑定期间其他调用可能已登记了服务器，在登记的同一次加锁中再检查一次上限，
+    // 并发启动的服务器因此不会超出上限；检查失败时监听器随之释放
+    {
+        let mut servers = lock_recover(&state.servers);
+        check_capacity(&servers, port, state)?;
+        let task = tokio::spawn(run_oauth_server(listener, ipv6_listener, sink, state.servers.clone(), context.clone()));
+        servers.insert(port, ServerHandle { task, context });
+    }
+    Ok(StartResult { port, redirect_uri })
+}
+
+// 只统计其他端口上仍在运行的服务器，被替换的同端口服务器不计入
+fn check_capacity(servers: &HashMap<u16, ServerHandle>, port: u16, state: &OAuthServerState) -> Result<(), OAuthError> {
+    let max_servers = lock_recover(&state.max_servers).unwrap_or(DEFAULT_MAX_SERVERS);
+    let running = servers
+        .iter()
+        .filter(|(running_port, handle)| **running_port != port && !handle.is_finished())
+        .count();
+    if running >= max_servers {
+        return Err(OAuthError::TooManyServers(format!(
+            "too_many_servers: {} servers already running",
+            running
+        )));
+    }
     Ok(())
 }
 
+// 绑定监听地址并记录实际地址；未指定监听地址时同时监听 IPv6 回环地址，部分系统会把 localhost 解析为 ::1
+// IPv6 不可用时只监听 IPv4
+async fn bind_listeners(
+    addr: SocketAddr,
+    context: &ServerContext,
+) -> Result<(TcpListener, Option<TcpListener>), OAuthError> {
+    let listener = bind_listener(addr).await?;
+    let local_addr = listener
+        .local_addr()
+        .map_err(|e| format!("failed to read bound address: {}", e))?;
+    let port = local_addr.port();
+    
+    let ipv6_listener = match context.options.host {
+        None => match bind_listener(SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), port)).await {
+            Ok(listener) => Some(listener),
+            Err(message) => {
+                warn!(port, error = %message, "IPv6 loopback unavailable, listening on IPv4 only");
+                None
+            }
+        },
+        Some(_) => None,
+    };
+    
+    let mut local_addrs = lock_recover(&context.local_addrs);
+    local_addrs.push(local_addr);
+    local_addrs.extend(ipv6_listener.as_ref().and_then(|listener| listener.local_addr().ok()));
+    Ok((listener, ipv6_listener))
+}
+
 #[command]
 async fn stop_oauth_server(
+    port: Option<u16>,
+    state: State<'_, OAuthServerState>,
+) -> Result<(), OAuthError> {
+    let handles: Vec<ServerHandle> = {
+        let mut servers = lock_recover(&state.servers);
+        
+        match port {
+            // 指定端口时只停止该端口的服务器
+            Some(port) => vec![servers
+                .remove(&port)
+                .ok_or_else(|| OAuthError::NotFound(format!("no server running on port {}", port)))?],
+            // 停止所有服务器
+            None => servers.drain().map(|(_, handle)| handle).collect(),
+        }
+    };
+    
+    // 先通知全部服务器退出，再逐个等待，使等待时间相互重叠
+    for handle in &handles {
+        handle.context.shutdown.cancel();
+    }
+    for handle in handles {
+        handle.stop().await;
+    }
+    
+    Ok(())
+}
+
+// 停止并移除指定端口的服务器，端口上没有服务器时什么也不做
+async fn shutdown_server(state: &OAuthServerState, port: u16) {
+    let handle = lock_recover(&state.servers).remove(&port);
+    if let Some(handle) = handle {
+        handle.stop().await;
+    }
+}
+
+// 按流程停止：停止该 flow_id 的全部服务器，取消对应的 oauth_login，并丢弃属于该流程的暂存回调
+// 其他流程的服务器不受影响；没有任何服务器或登录属于该流程时返回 not_found 错误
+#[command]
+async fn stop_oauth_flow(flow_id: String, state: State<'_, OAuthServerState>) -> Result<(), OAuthError> {
+    let login = lock_recover(&state.logins).remove(&flow_id);
+    if let Some(cancel) = &login {
+        cancel.cancel();
+    }
+    let stopped = shutdown_flow(&state, &flow_id).await;
+    
+    {
+        let mut pending = lock_recover(&state.pending_callback);
+        if pending.as_ref().is_some_and(|payload| payload["flow_id"].as_str() == Some(flow_id.as_str())) {
+            pending.take();
+        }
+    }
+    
+    if login.is_none() && stopped == 0 {
+        return Err(OAuthError::NotFound(format!("no flow found with id {}", flow_id)));
+    }
+    info!(flow_id = %flow_id, servers_stopped = stopped, "Stopped OAuth flow");
+    Ok(())
+}
+
+// 停止属于指定流程的所有服务器，返回停止的数量
+async fn shutdown_flow(state: &OAuthServerState, flow_id: &str) -> usize {
+    let handles: Vec<ServerHandle> = {
+        let mut servers = lock_recover(&state.servers);
+        let ports: Vec<u16> = servers
+            .iter()
+            .filter(|(_, handle)| handle.context.flow_id.as_deref() == Some(flow_id))
+            .map(|(port, _)| *port)
+            .collect();
+        ports.iter().filter_map(|port| servers.remove(port)).collect()
+    };
+    let count = handles.len();
+    for handle in &handles {
+        handle.context.shutdown.cancel();
+    }
+    for handle in handles {
+        handle.stop().await;
+    }
+    count
+}
+
+// 登记一个等待指定端口回调结果的接收端；服务器停止时接收端会收到关闭错误
+fn wait_for_callback(
+    state: &OAuthServerState,
+    port: u16,
+) -> Result<oneshot::Receiver<CallbackOutcome>, OAuthError> {
+    let servers = lock_recover(&state.servers);
+    let handle = servers
+        .get(&port)
+        .ok_or_else(|| OAuthError::NotFound(format!("no server running on port {}", port)))?;
+    
+    let (tx, rx) = oneshot::channel();
+    *lock_recover(&handle.context.callback_waiter) = Some(tx);
+    Ok(rx)
+}
+
+// 探测回环地址上的端口当前是否可以绑定，探测用的监听器立即释放
+#[command]
+async fn port_is_available(port: u16) -> bool {
+    probe_port(port).await
+}
+
+// 在 [start, end] 范围内按顺序查找第一个可以绑定的端口，便于前端预先登记对应的回调地址
+#[command]
+async fn find_free_port(start: u16, end: u16) -> Option<u16> {
+    for port in start.max(1)..=end {
+        if probe_port(port).await {
+            return Some(port);
+        }
+    }
+    None
+}
+
+async fn probe_port(port: u16) -> bool {
+    TcpListener::bind((Ipv4Addr::LOCALHOST, port)).await.is_ok()
+}
+
+// clear_all_oauth_data 的清除结果
+#[derive(Debug, Serialize)]
+struct ClearSummary {
+    // 删除了令牌的提供商
+    providers: Vec<String>,
+    flows_removed: usize,
+    servers_stopped: usize,
+    logins_cancelled: usize,
+    events_cleared: usize,
+    pending_callback_cleared: bool,
+}
+
+// 完全退出登录：停止全部服务器和进行中的登录，删除钥匙串中的令牌和保存的流程，清空暂存的回调和事件历史
+#[command]
+async fn clear_all_oauth_data(
     state: State<'_, OAuthServerState>,
-) -> Result<(), String> {
-    let mut servers = state.lock().map_err(|e| e.to_string())?;
+    app: tauri::AppHandle,
+) -> Result<ClearSummary, OAuthError> {
+    let logins: Vec<CancellationToken> = lock_recover(&state.logins).drain().map(|(_, cancel)| cancel).collect();
+    for cancel in &logins {
+        cancel.cancel();
+    }
     
-    // 停止所有服务器
-    for (_, handle) in servers.drain() {
-        handle.abort();
+    let handles: Vec<ServerHandle> = lock_recover(&state.servers).drain().map(|(_, handle)| handle).collect();
+    let servers_stopped = handles.len();
+    for handle in &handles {
+        handle.context.shutdown.cancel();
+    }
+    for handle in handles {
+        handle.stop().await;
     }
     
+    let pending_callback_cleared = lock_recover(&state.pending_callback).take().is_some();
+    let pending_callback_cleared = pending::clear_persisted(&app)? || pending_callback_cleared;
+    let events_cleared = {
+        let mut history = lock_recover(&state.event_history);
+        let count = history.len();
+        history.clear();
+        count
+    };
+    let flows_removed = flows::clear_flows(&app)?;
+    let providers = keychain::delete_all_tokens()?;
+    
+    info!(providers = providers.len(), flows_removed, servers_stopped, "Cleared all OAuth data");
+    Ok(ClearSummary {
+        providers,
+        flows_removed,
+        servers_stopped,
+        logins_cancelled: logins.len(),
+        events_cleared,
+        pending_callback_cleared,
+    })
+}
+
+// 设置同时运行的服务器数量上限，传入 None 时恢复默认值，已在运行的服务器不受影响
+#[command]
+fn set_max_oauth_servers(max: Option<usize>, state: State<'_, OAuthServerState>) -> Result<(), OAuthError> {
+    if max == Some(0) {
+        return Err("max must be at least 1".into());
+    }
+    *lock_recover(&state.max_servers) = max;
     Ok(())
 }
 
-async fn run_oauth_server(port: u16, app: tauri::AppHandle) -> Result<(), Box<dyn std::error::Error>> {
-    let listener = TcpListener::bind(format!("127.0.0.1:{}", port)).await?;
-    println!("OAuth callback server listening on port {}", port);
-    
-    while let Ok((mut stream, _)) = listener.accept().await {
-        let app_clone = app.clone();
-        tokio::spawn(async move {
-            let mut buffer = [0; 1024];
-            if let Ok(n) = stream.read(&mut buffer).await {
-                let request = String::from_utf8_lossy(&buffer[..n]);
-                
-                // 解析 HTTP 请求
-                if let Some(first_line) = request.lines().next() {
-                    if let Some(path) = first_line.split_whitespace().nth(1) {
-                        if path.starts_with("/callback/") {
-                            // 解析回调参数
-                            if let Some(query_start) = path.find('?') {
-                                let query = &path[query_start + 1..];
-                                let params: HashMap<String, String> = query
-                                    .split('&')
-                                    .filter_map(|pair| {
-                                        let mut parts = pair.split('=');
-                                        if let (Some(key), Some(value)) = (parts.next(), parts.next()) {
-                                            Some((key.to_string(), urlencoding::decode(value).unwrap_or_default().to_string()))
-                                        } else {
-                                            None
-                                        }
-                                    })
-                                    .collect();
-                                
-                                // 提取提供商
-                                let provider = path.split('/').nth(2).unwrap_or("unknown");
-                                
-                                // 发送事件到前端
-                                let payload = json!({
-                                    "provider": provider,
-                                    "code": params.get("code"),
-                                    "state": params.get("state"),
-                                    "error": params.get("error"),
-                                    "error_description": params.get("error_description")
-                                });
-                                
-                                if let Err(e) = app_clone.emit("oauth-callback", payload) {
-                                    eprintln!("Failed to emit oauth-callback event: {}", e);
-                                }
-                            }
-                        }
-                        
-                        // 发送响应
-                        let response = "HTTP/1.1 200 OK\r\n\r\n<html><body><h1>认证完成</h1><p>您可以关闭此窗口</p><script>window.close();</script></body></html>";
-                        let _ = stream.write_all(response.as_bytes()).await;
-                    }
+// 返回最近的 oauth-callback / oauth-callback-error 载荷，最新的在前，供调试面板展示
+#[command]
+fn oauth_event_history(state: State<'_, OAuthServerState>) -> Vec<serde_json::Value> {
+    lock_recover(&state.event_history).iter().cloned().collect()
+}
+
+// 在 Rust 侧生成随机 state 并登记为该端口服务器预期的 state，返回值由前端放入授权地址
+// 之后的回调自动按它校验，前端无需也无法遗漏 state 的设置与比对；再次调用会替换之前生成的 state
+#[command]
+fn new_oauth_state(port: u16, state: State<'_, OAuthServerState>) -> Result<String, OAuthError> {
+    let servers = lock_recover(&state.servers);
+    let handle = servers
+        .get(&port)
+        .filter(|handle| !handle.is_finished())
+        .ok_or_else(|| OAuthError::NotFound(format!("no server running on port {}", port)))?;
+    
+    let oauth_state = pkce::random_state();
+    *lock_recover(&handle.context.issued_state) = Some(oauth_state.clone());
+    Ok(oauth_state)
+}
+
+// 取出未能送达前端的回调载荷，取出后即清空
+// 先检查上次退出时写入磁盘的载荷，文件读取后即删除；文件无法读取时记录警告并继续返回内存中的载荷
+#[command]
+fn take_pending_oauth_callback(
+    state: State<'_, OAuthServerState>,
+    app: tauri::AppHandle,
+) -> Option<serde_json::Value> {
+    match pending::take_persisted(&app) {
+        Ok(Some(payload)) => return Some(payload),
+        Ok(None) => {}
+        Err(e) => warn!(error = %e, "Failed to read persisted OAuth callback"),
+    }
+    lock_recover(&state.pending_callback).take()
+}
+
+#[command]
+async fn list_oauth_servers(
+    state: State<'_, OAuthServerState>,
+) -> Result<Vec<u16>, OAuthError> {
+    let servers = lock_recover(&state.servers);
+    
+    // 只返回仍在运行的服务器
+    let mut ports: Vec<u16> = servers
+        .iter()
+        .filter(|(_, handle)| !handle.is_finished())
+        .map(|(port, _)| *port)
+        .collect();
+    ports.sort_unstable();
+    
+    Ok(ports)
+}
+
+#[command]
+async fn oauth_server_status(
+    port: u16,
+    state: State<'_, OAuthServerState>,
+) -> Result<ServerStatus, OAuthError> {
+    let servers = lock_recover(&state.servers);
+    servers
+        .get(&port)
+        .map(ServerHandle::status)
+        .ok_or_else(|| OAuthError::NotFound(format!("no server running on port {}", port)))
+}
+
+// 汇总所有已登记服务器的计数，供诊断面板一次性获取
+#[command]
+async fn oauth_metrics(state: State<'_, OAuthServerState>) -> Result<Metrics, OAuthError> {
+    let servers = lock_recover(&state.servers);
+    let statuses: Vec<ServerStatus> = servers.values().map(ServerHandle::status).collect();
+    
+    Ok(Metrics {
+        total_servers: statuses.len(),
+        total_callbacks: statuses.iter().map(|status| status.callbacks_received).sum(),
+        total_errors: statuses.iter().map(|status| status.errors_emitted).sum(),
+        oldest_uptime_secs: statuses.iter().map(|status| status.uptime_secs).max(),
+    })
+}
+
+async fn run_oauth_server(
+    listener: TcpListener,
+    ipv6_listener: Option<TcpListener>,
+    sink: Arc<dyn sink::CallbackSink>,
+    servers: ServerTable,
+    context: Arc<ServerContext>,
+) {
+    let addr = lock_recover(&context.local_addrs)[0];
+    let port = addr.port();
+    info!(port, host = %addr.ip(), ipv6 = ipv6_listener.is_some(), "OAuth callback server listening");
+    context.alive.store(true, Ordering::Relaxed);
+    
+    // 每次成功启动只发送一次，前端据此显示"等待授权"状态
+    let payload = json!({
+        "flow_id": context.flow_id,
+        "port": port
+    });
+    if let Err(e) = sink.emit("oauth-server-ready", &payload) {
+        error!(port, error = %e, "Failed to emit oauth-server-ready event");
+    }
+    
+    if context.options.diagnose {
+        tokio::spawn(diagnose_loopback(addr.ip(), port, sink.clone(), context.clone()));
+    }
+    
+    if let Some(secs) = context.options.flow_timeout_secs {
+        tokio::spawn(expire_flow(port, Duration::from_secs(secs), sink.clone(), context.clone()));
+    }
+    
+    // 回调事件经有界队列交给单个任务按到达顺序逐个发送，队列满时连接处理方等待
+    let (emits, queued) = mpsc::channel(context.security.emit_queue_capacity);
+    let emitter = tokio::spawn(drain_emits(queued, sink.clone(), context.clone()));
+    
+    // 跟踪进行中的连接，退出前等待它们写完响应
+    let connections = TaskTracker::new();
+    let connection_limit = Arc::new(Semaphore::new(context.security.max_connections));
+    let shutdown = &context.shutdown;
+    let mut accept_failures: u32 = 0;
+    
+    loop {
+        // 连接数达到上限时暂停 accept，新连接留在内核队列中等待
+        let permit = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            permit = connection_limit.clone().acquire_owned() => match permit {
+                Ok(permit) => permit,
+                Err(_) => break,
+            },
+        };
+        
+        let accepted = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            accepted = listener.accept() => accepted,
+            accepted = accept_optional(ipv6_listener.as_ref()) => accepted,
+        };
+        
+        // accept 出错（如文件描述符耗尽）通常是暂时的，按指数退避重试而不是退出
+        let (stream, peer) = match accepted {
+            Ok(accepted) => {
+                accept_failures = 0;
+                accepted
+            }
+            Err(e) => {
+                accept_failures += 1;
+                let backoff = ACCEPT_BACKOFF_INITIAL
+                    .saturating_mul(1 << accept_failures.min(16))
+                    .min(ACCEPT_BACKOFF_MAX);
+                warn!(port, error = %e, failures = accept_failures, "OAuth server accept failed, backing off");
+                if accept_failures == ACCEPT_FAILURES_BEFORE_REPORT {
+                    let message = format!("accept on port {} keeps failing: {}", port, e);
+                    emit_server_error(sink.as_ref(), port, context.flow_id.as_deref(), &message);
+                    context.errors_emitted.fetch_add(1, Ordering::Relaxed);
+                }
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    _ = tokio::time::sleep(backoff) => continue,
                 }
             }
+        };
+        
+        let emits = emits.clone();
+        let context = context.clone();
+        connections.spawn(async move {
+            context.in_flight.fetch_add(1, Ordering::Relaxed);
+            serve_connection(stream, peer, emits, context.clone()).await;
+            context.in_flight.fetch_sub(1, Ordering::Relaxed);
+            drop(permit);
         });
     }
     
+    // 停止接受新连接，等待已有连接处理完毕
+    drop(listener);
+    drop(ipv6_listener);
+    connections.close();
+    connections.wait().await;
+    // 所有连接结束后关闭队列，发送任务发完剩余事件后退出
+    drop(emits);
+    let _ = emitter.await;
+    context.alive.store(false, Ordering::Relaxed);
+    
+    // 自动停止时需要自行从状态中移除；手动停止的服务器在此之前已被移除，
+    // 端口上若登记了新的服务器，其令牌不会处于取消状态
+    {
+        let mut servers = lock_recover(&servers);
+        if servers.get(&port).is_some_and(|handle| handle.context.shutdown.is_cancelled()) {
+            servers.remove(&port);
+        }
+    }
+    
+    info!(port, "OAuth callback server stopped");
+    let payload = json!({
+        "flow_id": context.flow_id,
+        "port": port
+    });
+    if let Err(e) = sink.emit("oauth-server-stopped", &payload) {
+        error!(port, error = %e, "Failed to emit oauth-server-stopped event");
+    }
+}
+
+// 流程超时后通知前端并停止服务器；服务器先停止或收到成功回调时计时结束
+async fn expire_flow(port: u16, timeout: Duration, sink: Arc<dyn sink::CallbackSink>, context: Arc<ServerContext>) {
+    tokio::select! {
+        _ = context.shutdown.cancelled() => return,
+        _ = context.flow_completed.cancelled() => return,
+        _ = tokio::time::sleep(timeout) => {}
+    }
+    
+    info!(port, timeout_secs = timeout.as_secs(), "OAuth flow timed out");
+    let payload = json!({
+        "flow_id": context.flow_id,
+        "port": port
+    });
+    if let Err(e) = sink.emit("oauth-flow-timeout", &payload) {
+        error!(port, error = %e, "Failed to emit oauth-flow-timeout event");
+    }
+    context.shutdown.cancel();
+}
+
+// 自检：像浏览器一样连接服务器自身的端口，超时或被拒绝说明回环连接被拦截
+async fn diagnose_loopback(ip: IpAddr, port: u16, sink: Arc<dyn sink::CallbackSink>, context: Arc<ServerContext>) {
+    let ip = match ip {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    // 服务器在自检完成前停止时连接必然失败，不应报告为被拦截
+    let result = tokio::select! {
+        _ = context.shutdown.cancelled() => return,
+        result = tokio::time::timeout(LOOPBACK_DIAGNOSE_TIMEOUT, probe_health(ip, port, context.tls.is_some())) => result,
+    };
+    let error = match result {
+        Ok(Ok(())) => {
+            debug!(port, "Loopback self-test succeeded");
+            return;
+        }
+        Ok(Err(e)) => e,
+        Err(_) => format!("no connection within {}s", LOOPBACK_DIAGNOSE_TIMEOUT.as_secs()),
+    };
+    
+    warn!(port, error = %error, "Loopback self-test failed, callbacks may be blocked");
+    let payload = json!({
+        "flow_id": context.flow_id,
+        "port": port,
+        "code": "loopback_blocked",
+        "message": format!("could not connect to the callback server on port {}: {}", port, error)
+    });
+    context.errors_emitted.fetch_add(1, Ordering::Relaxed);
+    if let Err(e) = sink.emit("oauth-server-error", &payload) {
+        error!(port, error = %e, "Failed to emit oauth-server-error event");
+    }
+}
+
+// 连接回调服务器并请求健康检查路径；启用 TLS 时只确认能建立连接
+async fn probe_health(ip: IpAddr, port: u16, tls: bool) -> Result<(), String> {
+    let mut stream = TcpStream::connect((ip, port)).await.map_err(|e| e.to_string())?;
+    if tls {
+        return Ok(());
+    }
+    
+    let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", HEALTH_PATH, SocketAddr::new(ip, port));
+    stream.write_all(request.as_bytes()).await.map_err(|e| e.to_string())?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.map_err(|e| e.to_string())?;
+    if !response.starts_with(b"HTTP/1.1 200") {
+        let status_line = response.split(|&b| b == b'\r').next().unwrap_or_default();
+        return Err(format!("unexpected health check response: {}", String::from_utf8_lossy(status_line)));
+    }
     Ok(())
 }
 
+// 没有该监听器时永远不返回，便于在 select! 中统一处理多个监听器
+async fn accept_optional(listener: Option<&TcpListener>) -> std::io::Result<(TcpStream, SocketAddr)> {
+    match listener {
+        Some(listener) => listener.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+// 端口被占用时返回 PortInUse，前端可以据此换一个端口重试
+async fn bind_listener(addr: SocketAddr) -> Result<TcpListener, OAuthError> {
+    TcpListener::bind(addr).await.map_err(|e| {
+        let message = format!("failed to bind port {}: {}", addr.port(), e);
+        match e.kind() {
+            std::io::ErrorKind::AddrInUse => OAuthError::PortInUse(message),
+            _ => OAuthError::Internal(message),
+        }
+    })
+}
+
+// 启用 TLS 时先完成握手，再交给 handle_connection 处理
+async fn serve_connection(
+    stream: TcpStream,
+    peer: SocketAddr,
+    emits: EmitQueue,
+    context: Arc<ServerContext>,
+) {
+    // 空闲连接尽早关闭并释放名额，见 SecurityConfig.idle_timeout_secs
+    match tokio::time::timeout(context.security.idle_timeout(), stream.readable()).await {
+        Ok(Ok(())) => {}
+        Ok(Err(_)) => return,
+        Err(_) => {
+            debug!(%peer, "OAuth callback connection closed after staying idle");
+            return;
+        }
+    }
+    
+    let Some(acceptor) = context.tls.clone() else {
+        handle_connection(stream, peer, emits, context).await;
+        return;
+    };
+    
+    match tokio::time::timeout(context.security.read_timeout(), acceptor.accept(stream)).await {
+        Ok(Ok(stream)) => handle_connection(stream, peer, emits, context).await,
+        Ok(Err(e)) => warn!(error = %e, "OAuth callback TLS handshake failed"),
+        Err(_) => warn!("OAuth callback TLS handshake timed out"),
+    }
+}
+
+// 处理单个回调连接：读取请求、校验方法与路径，并写回响应
+async fn handle_connection<S>(mut stream: S, peer: SocketAddr, emits: EmitQueue, context: Arc<ServerContext>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let options = &context.options;
+    
+    // 客户端连接后迟迟不发送完整请求时，超时关闭连接，避免任务长期挂起
+    let read_timeout = context.security.read_timeout();
+    let read = read_request_head(&mut stream, context.security.max_request_bytes);
+    let buffer = match tokio::time::timeout(read_timeout, read).await {
+        Ok(Ok(RequestHead::Complete(buffer))) => buffer,
+        Ok(Ok(RequestHead::TooLarge)) => {
+            let response = http_response("413 Payload Too Large", &[], "text/plain; charset=utf-8", "Payload Too Large");
+            write_response(&mut stream, &response).await;
+            return;
+        }
+        Ok(Ok(RequestHead::Incomplete)) => {
+            debug!(%peer, "OAuth callback connection closed before the request head was complete");
+            return;
+        }
+        Ok(Err(_)) => return,
+        Err(_) => {
+            warn!(timeout_secs = read_timeout.as_secs(), "OAuth callback connection timed out");
+            let _ = stream.shutdown().await;
+            return;
+        }
+    };
+    
+    let mut request = match parse_http_request(&buffer) {
+        Ok(request) => request,
+        Err(e) => {
+            warn!(error = %e, "Failed to parse OAuth callback request");
+            let response = http_response("400 Bad Request", &[], "text/plain; charset=utf-8", "Bad Request");
+            write_response(&mut stream, &response).await;
+            return;
+        }
+    };
+    
+    // response_mode=form_post 时参数在请求体中，读取后按查询参数同样处理
+    if request.is_form_post() {
+        let length = request.content_length().unwrap_or_default();
+        if length > context.security.max_request_bytes {
+            let response = http_response("413 Payload Too Large", &[], "text/plain; charset=utf-8", "Payload Too Large");
+            write_response(&mut stream, &response).await;
+            return;
+        }
+        match tokio::time::timeout(read_timeout, read_body(&mut stream, &buffer, length)).await {
+            Ok(Ok(body)) => request.set_form_body(&body),
+            Ok(Err(e)) => {
+                debug!(error = %e, "Failed to read OAuth callback body");
+                return;
+            }
+            Err(_) => {
+                warn!(timeout_secs = read_timeout.as_secs(), "OAuth callback body read timed out");
+                let _ = stream.shutdown().await;
+                return;
+            }
+        }
+    }
+    
+    // 健康检查优先于回调路径匹配，不计入限流，也不发出任何事件
+    if request.method == "GET" && request.path == HEALTH_PATH {
+        let port = lock_recover(&context.local_addrs).first().map(SocketAddr::port).unwrap_or_default();
+        let body = json!({
+            "status": "ok",
+            "port": port,
+            "uptime_secs": context.started_at.elapsed().as_secs()
+        });
+        let response = http_response("200 OK", &[], "application/json", &body.to_string());
+        write_response(&mut stream, &response).await;
+        return;
+    }
+    
+    if request.method == "GET" && request.path.starts_with(assets::STATIC_PREFIX) {
+        if let Some(dir) = &options.success_asset_dir {
+            let response = assets::serve(dir, &request.path).await;
+            write_response(&mut stream, &response).await;
+            return;
+        }
+    }
+    
+    // 片段回传请求按其携带的回调路径处理，参数已在请求体中
+    let fragment = match options.match_fragment(&request) {
+        Some(path) => {
+            request.path = path.to_string();
+            true
+        }
+        None => false,
+    };
+    
+    // 只有 GET 回调和表单 POST 回调才会触发事件，浏览器顺带请求的 /favicon.ico 等直接返回 404
+    let mut completed = false;
+    let response = if request.method != "GET" && !request.is_form_post() {
+        http_response("405 Method Not Allowed", &[("Allow", "GET, POST")], "text/plain; charset=utf-8", "Method Not Allowed")
+    } else if let Some(provider) = options.match_callback(&request.path) {
+        if context.allow_callback(peer.ip()) {
+            context.callbacks_received.fetch_add(1, Ordering::Relaxed);
+            let provider = normalize_provider(provider.unwrap_or_default());
+            completed = handle_callback(&provider, &request, fragment, peer, &emits, &context).await;
+            let redirect = options.success_redirect().ok().flatten().filter(|_| completed);
+            if let Some(location) = redirect {
+                http_response("302 Found", &[("Location", &location)], "text/plain; charset=utf-8", "Found")
+            } else if options.allows_path(&request.path) {
+                http_response("200 OK", &[], "text/html; charset=utf-8", &options.success_html())
+            } else {
+                http_response("400 Bad Request", &[], "text/plain; charset=utf-8", "Invalid redirect URI")
+            }
+        } else {
+            warn!(peer = %peer, "Rate limited OAuth callback");
+            http_response("429 Too Many Requests", &[("Retry-After", "1")], "text/plain; charset=utf-8", "Too Many Requests")
+        }
+    } else {
+        debug!(path = %request.path, user_agent = request.header("User-Agent"), "Ignoring non-callback request");
+        http_response("404 Not Found", &[], "text/plain; charset=utf-8", "Not Found")
+    };
+    
+    write_response(&mut stream, &response).await;
+    
+    // 拿到授权码后服务器已完成使命；纯错误回调不停止，以便用户重试
+    if completed {
+        context.flow_completed.cancel();
+        if options.auto_stop() {
+            context.shutdown.cancel();
+        }
+    }
+}
+
+// 回调成功后关闭浏览器标签页的脚本
+const CLOSE_WINDOW_SCRIPT: &str = "<script>window.close();</script>";
+
+// 健康检查路径，供回环自检和外部探测确认服务器存活
+const HEALTH_PATH: &str = "/healthz";
+
+// 片段回传请求的路径前缀，其后为原回调路径，如 /fragment/callback/github
+const FRAGMENT_PATH: &str = "/fragment";
+
+// 读取 URL 片段，含有 error / access_token / id_token 时以表单回传，并从地址栏中去掉片段
+// 使用 sendBeacon，随后关闭窗口也不会中断请求
+const FRAGMENT_CAPTURE_SCRIPT: &str = "<script>(function(){var h=window.location.hash.slice(1);if(!h)return;var p=new URLSearchParams(h);if(!(p.has('error')||p.has('access_token')||p.has('id_token')))return;navigator.sendBeacon('/fragment'+window.location.pathname,new Blob([p.toString()],{type:'application/x-www-form-urlencoded'}));history.replaceState(null,'',window.location.pathname+window.location.search);})();</script>";
+
+// 默认的中英双语回调成功页面
+const DEFAULT_SUCCESS_HTML: &str = "<html><head><meta charset=\"utf-8\"><title>Authentication complete</title></head><body><h1>Authentication complete / 认证完成</h1><p>You can close this window. / 您可以关闭此窗口。</p><script>window.close();</script></body></html>";
+
+// 解析回调参数并通知前端，返回是否成功拿到授权码
+async fn handle_callback(
+    provider: &str,
+    request: &HttpRequest,
+    fragment: bool,
+    peer: SocketAddr,
+    emits: &EmitQueue,
+    context: &ServerContext,
+) -> bool {
+    // 开启 capture_fragment 时无查询串的请求是回传片段前的落地页；否则按缺少授权码处理
+    let query = match request.raw_query.as_deref() {
+        Some(query) => query,
+        None if context.options.capture_fragment && !fragment => return false,
+        None => "",
+    };
+    
+    // 重复的回调仍会收到成功页面以便浏览器关闭标签页，但不再通知前端
+    if context.is_duplicate_callback(query) {
+        info!("Ignoring duplicate OAuth callback");
+        return false;
+    }
+    
+    // 成功与失败分别通过 oauth-callback 和 oauth-callback-error 通知前端
+    let issued_state = lock_recover(&context.issued_state).clone();
+    let expected_state = issued_state.as_deref().or(context.options.expected_state.as_deref());
+    let outcome = callback_payload(provider, request, fragment, expected_state, &context.options);
+    let (event, mut payload, completed) = match outcome {
+        Ok(payload) => {
+            let completed = ["code", "access_token", "id_token"].iter().any(|key| payload[*key].is_string());
+            ("oauth-callback", payload, completed)
+        }
+        Err(payload) => {
+            context.errors_emitted.fetch_add(1, Ordering::Relaxed);
+            ("oauth-callback-error", payload, false)
+        }
+    };
+    payload["flow_id"] = json!(context.flow_id);
+    payload["raw_params"] = request.query_json();
+    payload["peer"] = json!(peer.to_string());
+    payload["received_at"] = json!(unix_millis());
+    payload["raw_path"] = json!(safe_decode(&request.target));
+    if context.options.log_callbacks {
+        log_callback(event, &payload);
+    }
+    
+    let waiter = lock_recover(&context.callback_waiter).take();
+    if let Some(waiter) = waiter {
+        let outcome = if event == "oauth-callback" {
+            Ok(payload.clone())
+        } else {
+            Err(payload.clone())
+        };
+        let _ = waiter.send(outcome);
+    }
+    
+    // 等待事件发送完成，之后才写出回调页面
+    let (done, delivered) = oneshot::channel();
+    match emits.send((event, payload, done)).await {
+        Ok(()) => {
+            let _ = delivered.await;
+        }
+        Err(_) => warn!(event, "OAuth callback event queue closed, dropping event"),
+    }
+    
+    completed
+}
+
+// 按入队顺序逐个发送回调事件，直到所有连接结束、队列关闭
+async fn drain_emits(
+    mut queued: mpsc::Receiver<(&'static str, serde_json::Value, oneshot::Sender<()>)>,
+    sink: Arc<dyn sink::CallbackSink>,
+    context: Arc<ServerContext>,
+) {
+    while let Some((event, payload, done)) = queued.recv().await {
+        emit_callback(sink.as_ref(), event, payload, &context.options).await;
+        let _ = done.send(());
+    }
+}
+
+// 发送回调事件；webview 尚未就绪导致发送失败时按配置间隔重试，全部失败则暂存载荷
+// 调用方在返回后才写出回调页面，浏览器页面的状态因此与事件是否送达一致
+async fn emit_callback(sink: &dyn sink::CallbackSink, event: &str, payload: serde_json::Value, options: &ServerOptions) {
+    sink.record(&payload);
+    let (retries, interval) = options.emit_retry().unwrap_or((DEFAULT_EMIT_RETRIES, Duration::from_millis(DEFAULT_EMIT_RETRY_INTERVAL_MS)));
+    
+    let mut result = sink.emit(event, &payload);
+    for attempt in 1..=retries {
+        if result.is_ok() {
+            return;
+        }
+        tokio::time::sleep(interval).await;
+        debug!(event, attempt, "Retrying OAuth callback event");
+        result = sink.emit(event, &payload);
+    }
+    
+    if let Err(e) = result {
+        warn!(event, error = %e, "Failed to emit OAuth callback event, buffering payload");
+        sink.buffer(payload);
+    }
+}
+
+// 构造回调事件载荷：成功时为 oauth-callback 的载荷，失败时为 oauth-callback-error 的载荷
+// 回调路径未登记、提供商不在允许列表中或 state 与预期不符时，分别以 invalid_redirect、unknown_provider、state_mismatch 错误代替授权码
+fn callback_payload(
+    provider: &str,
+    request: &HttpRequest,
+    fragment: bool,
+    expected_state: Option<&str>,
+    options: &ServerOptions,
+) -> Result<serde_json::Value, serde_json::Value> {
+    // 重复的参数以第一次出现为准，并去掉首尾空白
+    let param = |name| request.query_param(name).map(str::trim);
+    let state = param("state");
+    
+    if !options.allows_path(&request.path) {
+        warn!(path = %request.path, "Rejected OAuth callback: path not in allowed_paths");
+        return Err(json!({
+            "provider": provider,
+            "error": "invalid_redirect",
+            "error_description": "The callback path does not match any registered redirect URI",
+            "state": state
+        }));
+    }
+    
+    if !options.allows_provider(provider) {
+        warn!(provider, "Rejected OAuth callback: unknown provider");
+        return Err(json!({
+            "provider": provider,
+            "error": "unknown_provider",
+            "error_description": "The callback path names a provider that is not allowed for this server",
+            "state": state
+        }));
+    }
+    
+    if let Some(error) = param("error") {
+        return Err(json!({
+            "provider": provider,
+            "error": error,
+            "error_description": param("error_description"),
+            "state": state
+        }));
+    }
+    
+    if let Some(expected) = expected_state {
+        if state != Some(expected) {
+            warn!(provider, "Rejected OAuth callback: state mismatch");
+            return Err(json!({
+                "provider": provider,
+                "error": "state_mismatch",
+                "error_description": "The state parameter does not match the value generated for this flow",
+                "state": state
+            }));
+        }
+    }
+    
+    // 隐式流程的片段中是令牌而不是授权码
+    if fragment {
+        return Ok(json!({
+            "provider": provider,
+            "access_token": param("access_token"),
+            "id_token": param("id_token"),
+            "token_type": param("token_type"),
+            "expires_in": param("expires_in"),
+            "state": state,
+            "fragment": true
+        }));
+    }
+    
+    // 空授权码在换取令牌时才会以难以理解的方式失败，这里直接作为错误回调
+    let Some(code) = param("code").filter(|code| !code.is_empty()) else {
+        warn!(provider, "Rejected OAuth callback: missing authorization code");
+        return Err(json!({
+            "provider": provider,
+            "error": "missing_code",
+            "error_description": "The callback did not include an authorization code",
+            "state": state
+        }));
+    };
+    
+    Ok(json!({
+        "provider": provider,
+        "code": code,
+        "state": state
+    }))
+}
+
+// 记录脱敏后的回调；raw_params 和 raw_path 中同样含有 code 与 state，因此不记录
+fn log_callback(event: &str, payload: &serde_json::Value) {
+    let redacted = |key: &str| payload[key].as_str().map(redact);
+    info!(
+        event,
+        provider = payload["provider"].as_str(),
+        code = redacted("code"),
+        state = redacted("state"),
+        access_token = redacted("access_token"),
+        id_token = redacted("id_token"),
+        error = payload["error"].as_str(),
+        error_description = payload["error_description"].as_str(),
+        peer = payload["peer"].as_str(),
+        "OAuth callback"
+    );
+}
+
+// 只保留首 6 个和末 2 个字符，如 gho_ab…yz；不足 12 个字符的值整体隐藏，避免泄露大部分内容
+fn redact(secret: &str) -> String {
+    let chars: Vec<char> = secret.chars().collect();
+    if chars.len() < 12 {
+        return "…".to_string();
+    }
+    let head: String = chars[..6].iter().collect();
+    let tail: String = chars[chars.len() - 2..].iter().collect();
+    format!("{}…{}", head, tail)
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+// 解码并规范化路径中的提供商名称，缺失或为空时为 "unknown"
+fn normalize_provider(segment: &str) -> String {
+    let provider = safe_decode(segment).trim().to_lowercase();
+    if provider.is_empty() {
+        "unknown".to_string()
+    } else {
+        provider
+    }
+}
+
+// 通知前端回调服务器出错
+fn emit_server_error(sink: &dyn sink::CallbackSink, port: u16, flow_id: Option<&str>, message: &str) {
+    let payload = json!({
+        "flow_id": flow_id,
+        "port": port,
+        "message": message
+    });
+    
+    if let Err(e) = sink.emit("oauth-server-error", &payload) {
+        error!(port, error = %e, "Failed to emit oauth-server-error event");
+    }
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    // 默认输出 info 级别日志，可通过 RUST_LOG 环境变量调整
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .try_init();
+    
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
-        .manage(OAuthServerState::new(Mutex::new(HashMap::new())))
-        .invoke_handler(tauri::generate_handler![start_oauth_server, stop_oauth_server])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .manage(OAuthServerState::default())
+        .manage(client::HttpClient::new().expect("failed to build HTTP client"))
+        .setup(|app| {
+            // Ctrl+C 或 SIGTERM 时按正常流程退出，使 Exit 事件中的清理（包括保存暂存的回调）得以执行
+            let handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                termination_signal().await;
+                info!("Received termination signal, exiting");
+                handle.exit(0);
+            });
+            Ok(())
+        })
+        .invoke_handler(tauri::generate_handler![
+            start_oauth_server,
+            stop_oauth_server,
+            stop_oauth_flow,
+            restart_oauth_server,
+            list_oauth_servers,
+            oauth_server_status,
+            oauth_metrics,
+            set_max_oauth_servers,
+            port_is_available,
+            find_free_port,
+            take_pending_oauth_callback,
+            new_oauth_state,
+            oauth_event_history,
+            clear_all_oauth_data,
+            token::exchange_oauth_code,
+            token::refresh_oauth_token,
+            token::revoke_oauth_token,
+            pkce::generate_pkce_pair,
+            keychain::save_oauth_tokens,
+            keychain::load_oauth_tokens,
+            keychain::delete_oauth_tokens,
+            jwt::decode_id_token,
+            jwt::token_is_expired,
+            jwks::verify_id_token,
+            userinfo::fetch_userinfo,
+            login::oauth_login,
+            login::cancel_oauth_login,
+            login::validate_oauth_config,
+            login::build_authorize_url,
+            login::open_authorize,
+            device::start_device_flow,
+            device::poll_device_token,
+            flows::persist_oauth_flow,
+            flows::restore_oauth_flow,
+            api::authorized_request,
+            client::set_http_proxy,
+            client::register_trusted_hosts,
+            presets::provider_preset
+        ])
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            if let tauri::RunEvent::Exit = event {
+                stop_all_servers(app);
+                persist_pending_callback(app);
+            }
+        });
+}
+
+// 服务器全部停止后不会再有新的暂存载荷，此时把仍未取走的载荷写入磁盘
+fn persist_pending_callback(app: &tauri::AppHandle) {
+    let state = app.state::<OAuthServerState>();
+    let payload = lock_recover(&state.pending_callback).take();
+    if let Some(payload) = payload {
+        match pending::persist_pending(app, &payload) {
+            Ok(()) => info!("Saved pending OAuth callback for the next launch"),
+            Err(e) => error!(error = %e, "Failed to save pending OAuth callback"),
+        }
+    }
+}
+
+// 等待 Ctrl+C，Unix 下同时等待 SIGTERM；无法注册信号处理时永远不返回
+async fn termination_signal() {
+    let ctrl_c = async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    };
+    
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+// 应用退出时停止所有回调服务器，确保端口在运行时销毁前被释放
+fn stop_all_servers(app: &tauri::AppHandle) {
+    let state = app.state::<OAuthServerState>();
+    let handles: Vec<ServerHandle> = lock_recover(&state.servers).drain().map(|(_, handle)| handle).collect();
+    for handle in &handles {
+        handle.context.shutdown.cancel();
+    }
+    
+    // 退出过程中无法长时间等待，给监听循环一个短暂的宽限期后强制中止
+    tauri::async_runtime::block_on(async {
+        for handle in handles {
+            handle.stop().await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{get, loopback_options, send_request, start_server, RecordingSink};
+    
+    #[tokio::test]
+    async fn callback_is_delivered_end_to_end() {
+        let options = ServerOptions {
+            expected_state: Some("xyz".to_string()),
+            ..loopback_options()
+        };
+        let (port, sink, _state) = start_server(options, SecurityConfig::default(), RecordingSink::default()).await;
+        assert_eq!(sink.wait_for("oauth-server-ready", 1).await.len(), 1);
+        
+        let response = send_request(port, &get("/callback/github?code=abc&state=xyz")).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        
+        let callbacks = sink.events("oauth-callback");
+        assert_eq!(callbacks.len(), 1);
+        assert_eq!(callbacks[0]["provider"], "github");
+        assert_eq!(callbacks[0]["code"], "abc");
+        assert_eq!(callbacks[0]["state"], "xyz");
+        assert_eq!(callbacks[0]["flow_id"], "test-flow");
+        
+        // 拿到授权码后自动停止
+        let stopped = sink.wait_for("oauth-server-stopped", 1).await;
+        assert_eq!(stopped[0]["port"], port);
+    }
+    
+    #[tokio::test]
+    async fn shutdown_flow_stops_registered_server() {
+        let (port, sink, state) = start_server(loopback_options(), SecurityConfig::default(), RecordingSink::default()).await;
+        assert!(lock_recover(&state.servers).contains_key(&port));
+        
+        assert_eq!(shutdown_flow(&state, "test-flow").await, 1);
+        assert!(lock_recover(&state.servers).is_empty());
+        assert_eq!(sink.events("oauth-server-stopped").len(), 1);
+        assert!(TcpStream::connect(("127.0.0.1", port)).await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn callback_without_code_emits_missing_code() {
+        for target in ["/callback", "/callback?code=", "/callback?code=%20"] {
+            let (port, sink, _state) = start_server(loopback_options(), SecurityConfig::default(), RecordingSink::default()).await;
+            send_request(port, &get(target)).await;
+            
+            let errors = sink.wait_for("oauth-callback-error", 1).await;
+            assert_eq!(errors.len(), 1, "{}", target);
+            assert_eq!(errors[0]["error"], "missing_code", "{}", target);
+            assert!(sink.events("oauth-callback").is_empty(), "{}", target);
+        }
+    }
+    
+    #[tokio::test]
+    async fn fragment_landing_page_does_not_emit() {
+        let options = ServerOptions {
+            capture_fragment: true,
+            ..loopback_options()
+        };
+        let (port, sink, _state) = start_server(options, SecurityConfig::default(), RecordingSink::default()).await;
+        let response = send_request(port, &get("/callback")).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(sink.events("oauth-callback-error").is_empty());
+        assert!(sink.events("oauth-callback").is_empty());
+    }
+    
+    #[tokio::test]
+    async fn idle_connections_do_not_starve_callbacks() {
+        let security = SecurityConfig {
+            max_connections: 2,
+            idle_timeout_secs: 1,
+            ..Default::default()
+        };
+        let (port, sink, _state) = start_server(loopback_options(), security, RecordingSink::default()).await;
+        let mut idle = Vec::new();
+        for _ in 0..4 {
+            idle.push(TcpStream::connect(("127.0.0.1", port)).await.unwrap());
+        }
+        
+        // 空闲连接在 idle_timeout 后被关闭，回调不必等到 read_timeout
+        let response = send_request(port, &get("/callback?code=abc")).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert_eq!(sink.events("oauth-callback").len(), 1);
+    }
+    
+    #[tokio::test]
+    async fn concurrent_launches_respect_server_cap() {
+        let state = OAuthServerState::default();
+        *lock_recover(&state.max_servers) = Some(2);
+        let launch = |port, force| {
+            let sink = Arc::new(RecordingSink::default());
+            launch_server(port, None, loopback_options(), SecurityConfig::default(), force, &state, sink)
+        };
+        let replaced = launch(0, false).await.unwrap().port;
+        
+        // 替换旧服务器时要等待它停止，其间另外两个启动先完成登记
+        let results = tokio::join!(launch(replaced, true), launch(0, false), launch(0, false));
+        let results = [results.0, results.1, results.2];
+        let rejected = results
+            .iter()
+            .filter(|result| matches!(result, Err(OAuthError::TooManyServers(_))))
+            .count();
+        assert_eq!(rejected, 1);
+        assert_eq!(lock_recover(&state.servers).len(), 2);
+    }
+    
+    #[test]
+    fn success_redirect_rejects_header_injection() {
+        let options = ServerOptions {
+            success_redirect: Some("https://example.com/done\r\nSet-Cookie: a=b".to_string()),
+            ..Default::default()
+        };
+        assert!(options.success_redirect().is_err());
+        
+        let options = ServerOptions {
+            success_redirect: Some("https://example.com/done?x=1 2".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(options.success_redirect().unwrap().as_deref(), Some("https://example.com/done?x=1%202"));
+    }
+    
+    #[tokio::test]
+    async fn silent_connections_are_closed_after_timeout() {
+        let security = SecurityConfig {
+            read_timeout_secs: 1,
+            idle_timeout_secs: 1,
+            ..Default::default()
+        };
+        let (port, _sink, _state) = start_server(loopback_options(), security, RecordingSink::default()).await;
+        
+        // 什么都不发送，以及只发送一部分请求行
+        for sent in [&b""[..], &b"GET /cal"[..]] {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+            stream.write_all(sent).await.unwrap();
+            let mut response = Vec::new();
+            let closed = tokio::time::timeout(Duration::from_secs(3), stream.read_to_end(&mut response)).await;
+            assert!(closed.is_ok(), "connection was not closed after the timeout");
+            assert!(response.is_empty());
+        }
+    }
+    
+    fn request(target: &str) -> HttpRequest {
+        parse_http_request(get(target).as_bytes()).unwrap()
+    }
+    
+    #[test]
+    fn callback_payload_checks_state() {
+        let options = ServerOptions::default();
+        let matching = callback_payload("github", &request("/callback/github?code=abc&state=xyz"), false, Some("xyz"), &options);
+        assert_eq!(matching.unwrap()["code"], "abc");
+        
+        for target in ["/callback/github?code=abc&state=other", "/callback/github?code=abc"] {
+            let mismatched = callback_payload("github", &request(target), false, Some("xyz"), &options);
+            assert_eq!(mismatched.unwrap_err()["error"], "state_mismatch", "{}", target);
+        }
+    }
+    
+    #[tokio::test]
+    async fn excess_connections_wait_for_a_permit() {
+        let security = SecurityConfig {
+            max_connections: 2,
+            ..Default::default()
+        };
+        let (port, _sink, _state) = start_server(loopback_options(), security, RecordingSink::default()).await;
+        
+        // 超出名额的连接留在内核队列中，等前面的连接处理完后依次得到响应
+        let mut requests = tokio::task::JoinSet::new();
+        for _ in 0..8 {
+            requests.spawn(async move { send_request(port, &get("/favicon.ico")).await });
+        }
+        while let Some(response) = requests.join_next().await {
+            assert!(response.unwrap().starts_with("HTTP/1.1 404 Not Found"));
+        }
+        
+        // 略超过默认上限，服务器读完整个请求后再响应，关闭连接时不会因未读数据而重置
+        let oversized = format!("GET /callback?code={} HTTP/1.1\r\n\r\n", "x".repeat(8300));
+        assert!(send_request(port, &oversized).await.starts_with("HTTP/1.1 413 Payload Too Large"));
+    }
+    
+    #[test]
+    fn bind_ip_accepts_loopback_only() {
+        let options = |host: &str, allow_external| ServerOptions {
+            host: Some(host.to_string()),
+            allow_external,
+            ..Default::default()
+        };
+        assert_eq!(options("::1", false).bind_ip(), Ok(IpAddr::V6(Ipv6Addr::LOCALHOST)));
+        assert_eq!(options("[::1]", false).bind_ip(), Ok(IpAddr::V6(Ipv6Addr::LOCALHOST)));
+        assert_eq!(options("127.0.0.2", false).bind_ip(), Ok(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2))));
+        assert_eq!(options("::1", false).redirect_uri(8080), "http://[::1]:8080/callback/");
+        
+        assert!(options("192.168.1.5", false).bind_ip().unwrap_err().contains("allow_external"));
+        assert!(options("::", false).bind_ip().is_err());
+        assert_eq!(options("192.168.1.5", true).bind_ip(), Ok(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 5))));
+        assert!(options("localhost", false).bind_ip().is_err());
+    }
+    
+    #[tokio::test]
+    async fn duplicate_callback_emits_once() {
+        let options = ServerOptions {
+            auto_stop: Some(false),
+            ..loopback_options()
+        };
+        let (port, sink, _state) = start_server(options, SecurityConfig::default(), RecordingSink::default()).await;
+        for _ in 0..2 {
+            let response = send_request(port, &get("/callback/github?code=abc&state=xyz")).await;
+            assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        }
+        assert_eq!(sink.events("oauth-callback").len(), 1);
+        
+        send_request(port, &get("/callback/github?code=def&state=xyz")).await;
+        assert_eq!(sink.events("oauth-callback").len(), 2);
+    }
+    
+    // 持锁时 panic 使服务器表中毒后，启动、查询和停止服务器仍然可用
+    #[tokio::test]
+    async fn server_commands_survive_a_poisoned_lock() {
+        let state = OAuthServerState::default();
+        let servers = state.servers.clone();
+        let _ = std::thread::spawn(move || {
+            let _guard = servers.lock().unwrap();
+            panic!("poison the server table");
+        })
+        .join();
+        assert!(state.servers.is_poisoned());
+        
+        let sink = Arc::new(RecordingSink::default());
+        let started = launch_server(0, Some("poisoned".to_string()), loopback_options(), SecurityConfig::default(), false, &state, sink)
+            .await
+            .unwrap();
+        assert!(lock_recover(&state.servers).contains_key(&started.port));
+        assert_eq!(shutdown_flow(&state, "poisoned").await, 1);
+        assert!(lock_recover(&state.servers).is_empty());
+    }
+    
+    #[tokio::test]
+    async fn callback_burst_is_rate_limited() {
+        let options = ServerOptions {
+            auto_stop: Some(false),
+            ..loopback_options()
+        };
+        let security = SecurityConfig {
+            rate_limit_per_sec: 0.1,
+            rate_limit_burst: 3.0,
+            ..Default::default()
+        };
+        let (port, sink, _state) = start_server(options, security, RecordingSink::default()).await;
+        
+        let mut limited = 0;
+        for i in 0..6 {
+            let response = send_request(port, &get(&format!("/callback/github?code={}", i))).await;
+            if response.starts_with("HTTP/1.1 429") {
+                limited += 1;
+            }
+        }
+        assert_eq!(limited, 3);
+        assert_eq!(sink.events("oauth-callback").len(), 3);
+    }
+    
+    #[test]
+    fn allows_path_requires_exact_match() {
+        assert!(ServerOptions::default().allows_path("/callback/anything"));
+        
+        let options = ServerOptions {
+            allowed_paths: vec!["/callback/github".to_string()],
+            ..Default::default()
+        };
+        assert!(options.allows_path("/callback/github"));
+        for path in ["/callback/github/", "/callback/githubx", "/callback/GitHub", "/callback/google"] {
+            assert!(!options.allows_path(path), "{}", path);
+        }
+        
+        let rejected = callback_payload("google", &request("/callback/google?code=abc"), false, None, &options);
+        assert_eq!(rejected.unwrap_err()["error"], "invalid_redirect");
+    }
+    
+    // 发送缓慢时回调在有界队列中等待，全部按到达顺序送达；发送会阻塞线程，因此使用多线程运行时
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn callback_burst_is_delivered_in_order() {
+        let options = ServerOptions {
+            auto_stop: Some(false),
+            ..loopback_options()
+        };
+        let security = SecurityConfig {
+            emit_queue_capacity: 2,
+            rate_limit_burst: 100.0,
+            ..Default::default()
+        };
+        let sink = RecordingSink::with_delay(Duration::from_millis(50));
+        let (port, sink, _state) = start_server(options, security, sink).await;
+        sink.wait_for("oauth-server-ready", 1).await;
+        
+        // 每个请求间隔一段时间发出，保证到达顺序确定
+        let mut streams = Vec::new();
+        for i in 0..10 {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+            stream.write_all(get(&format!("/callback/github?code={}", i)).as_bytes()).await.unwrap();
+            streams.push(stream);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        for mut stream in streams {
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        }
+        
+        let codes: Vec<String> = sink.events("oauth-callback").iter().map(|payload| payload["code"].to_string()).collect();
+        let expected: Vec<String> = (0..10).map(|i| format!("\"{}\"", i)).collect();
+        assert_eq!(codes, expected);
+    }
+    
+    #[test]
+    fn redact_masks_the_middle() {
+        assert_eq!(redact("gho_abcdefghijklmnxyz"), "gho_ab…yz");
+        assert_eq!(redact("123456789012"), "123456…12");
+        assert_eq!(redact("12345678901"), "…");
+        assert_eq!(redact(""), "…");
+        assert_eq!(redact("授权码授权码授权码授权码"), "授权码授权码…权码");
+    }
+    
+    #[tokio::test]
+    async fn restart_keeps_issued_state() {
+        let (port, sink, state) = start_server(loopback_options(), SecurityConfig::default(), RecordingSink::default()).await;
+        let context = |state: &OAuthServerState| lock_recover(&state.servers)[&port].context.clone();
+        *lock_recover(&context(&state).issued_state) = Some("issued".to_string());
+        
+        restart_server(port, &state, sink.clone()).await.unwrap();
+        assert_eq!(lock_recover(&context(&state).issued_state).as_deref(), Some("issued"));
+        send_request(port, &get("/callback/github?code=abc&state=forged")).await;
+        assert_eq!(sink.wait_for("oauth-callback-error", 1).await[0]["error"], "state_mismatch");
+        
+        let missing = restart_server(1, &state, sink).await.unwrap_err();
+        assert!(matches!(missing, OAuthError::NotFound(_)), "{:?}", missing);
+    }
+    
+    #[tokio::test]
+    async fn reuse_requires_the_same_flow_id() {
+        let (port, sink, state) = start_server(loopback_options(), SecurityConfig::default(), RecordingSink::default()).await;
+        let launch = |flow_id: &str, force| {
+            launch_server(port, Some(flow_id.to_string()), loopback_options(), SecurityConfig::default(), force, &state, sink.clone())
+        };
+        sink.wait_for("oauth-server-ready", 1).await;
+        
+        assert_eq!(launch("test-flow", false).await.unwrap().port, port);
+        let conflict = launch("other-flow", false).await.unwrap_err();
+        assert!(matches!(conflict, OAuthError::PortInUse(_)), "{:?}", conflict);
+        
+        launch("other-flow", true).await.unwrap();
+        assert_eq!(lock_recover(&state.servers)[&port].context.flow_id.as_deref(), Some("other-flow"));
+        assert_eq!(shutdown_flow(&state, "other-flow").await, 1);
+    }
 }
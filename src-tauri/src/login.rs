@@ -0,0 +1,591 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tauri::{command, AppHandle, State};
+use tauri_plugin_opener::OpenerExt;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+use url::Url;
+
+use crate::client::HttpClient;
+use crate::error::OAuthError;
+use crate::sink::CallbackSink;
+use crate::{launch_server, lock_recover, pkce, presets, shutdown_flow, shutdown_server, token, userinfo, wait_for_callback};
+use crate::{OAuthServerState, SecurityConfig, ServerOptions};
+
+// 未指定提供商时回调路径中使用的名称
+const DEFAULT_PROVIDER: &str = "oauth";
+
+// oauth_login 所需的提供商配置
+#[derive(Debug, Clone, Deserialize)]
+pub struct OAuthConfig {
+    #[serde(default)]
+    pub provider: Option<String>,
+    pub authorize_url: String,
+    pub token_url: String,
+    #[serde(default)]
+    pub userinfo_url: Option<String>,
+    pub client_id: String,
+    #[serde(default)]
+    pub client_secret: Option<String>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    // 连接 scopes 的分隔符，通常传入 provider_preset 返回的 scope_delimiter；省略时按 provider 的预设，未知提供商使用空格
+    #[serde(default)]
+    pub scope_delimiter: Option<String>,
+    // 回调服务器端口，0 表示由系统分配
+    #[serde(default)]
+    pub port: u16,
+    // 只构造授权地址并返回，不打开浏览器也不等待回调，用于排查回调地址和 scope 配置
+    #[serde(default)]
+    pub dry_run: bool,
+    // dry_run 时是否仍启动回调服务器以便测试回环连接；启动的服务器需要调用方通过 stop_oauth_flow 停止
+    #[serde(default)]
+    pub dry_run_start_server: bool,
+}
+
+// oauth_login 的返回值，dry_run 时为构造出的授权参数
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum LoginOutcome {
+    Completed(LoginResult),
+    DryRun(DryRunResult),
+}
+
+#[derive(Debug, Serialize)]
+pub struct DryRunResult {
+    pub flow_id: String,
+    pub authorize_url: String,
+    pub redirect_uri: String,
+    pub state: String,
+    pub code_challenge: String,
+    // 启动了回调服务器时为其实际端口
+    pub port: Option<u16>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LoginResult {
+    pub flow_id: String,
+    pub provider: String,
+    pub tokens: Value,
+    pub userinfo: Option<Value>,
+}
+
+// 完整的登录流程：启动回调服务器、用 PKCE 和 state 构造授权地址并打开浏览器，
+// 等待回调后换取令牌并获取用户信息。用户关闭流程（停止服务器）或调用 cancel_oauth_login 时返回 cancelled 错误
+// 未传入 flow_id 时自动生成；前端需要取消时应自行传入，或从 oauth-server-ready 事件中获取
+// 设置 dry_run 时构造授权地址后立即返回 DryRunResult
+#[command]
+pub async fn oauth_login(
+    config: OAuthConfig,
+    flow_id: Option<String>,
+    state: State<'_, OAuthServerState>,
+    client: State<'_, HttpClient>,
+    app: AppHandle,
+) -> Result<LoginOutcome, OAuthError> {
+    check_hosts(&config, &client)?;
+    
+    let flow_id = flow_id.unwrap_or_else(pkce::random_state);
+    if config.dry_run {
+        return dry_run(&config, &flow_id, &state, &app).await.map(LoginOutcome::DryRun);
+    }
+    
+    let cancel = CancellationToken::new();
+    {
+        let mut logins = lock_recover(&state.logins);
+        if logins.contains_key(&flow_id) {
+            return Err(format!("login already in progress for flow {}", flow_id).into());
+        }
+        logins.insert(flow_id.clone(), cancel.clone());
+    }
+    
+    // 取消时先停止服务器再返回，之后不会再有该流程的回调事件
+    let client = client.get();
+    let result = tokio::select! {
+        result = login_flow(&config, &flow_id, &state, &client, Arc::new(app.clone()), &app) => result,
+        _ = cancel.cancelled() => {
+            shutdown_flow(&state, &flow_id).await;
+            Err(OAuthError::Cancelled)
+        }
+    };
+    
+    lock_recover(&state.logins).remove(&flow_id);
+    result.map(LoginOutcome::Completed)
+}
+
+// 授权地址会在浏览器中打开，与令牌和 userinfo 端点一样必须是可信主机
+fn check_hosts(config: &OAuthConfig, client: &HttpClient) -> Result<(), OAuthError> {
+    client.check_trusted(&config.authorize_url)?;
+    client.check_trusted(&config.token_url)?;
+    if let Some(userinfo_url) = &config.userinfo_url {
+        client.check_trusted(userinfo_url)?;
+    }
+    Ok(())
+}
+
+// 与 login_flow 使用相同的参数构造授权地址，但不打开浏览器也不等待回调
+// 不启动服务器时无法得知系统分配的端口，因此要求配置固定端口
+async fn dry_run(
+    config: &OAuthConfig,
+    flow_id: &str,
+    state: &OAuthServerState,
+    app: &AppHandle,
+) -> Result<DryRunResult, OAuthError> {
+    let provider = config
+        .provider
+        .clone()
+        .unwrap_or_else(|| DEFAULT_PROVIDER.to_string());
+    let oauth_state = pkce::random_state();
+    let challenge = pkce::code_challenge(&pkce::random_verifier());
+    
+    let options = ServerOptions {
+        expected_state: Some(oauth_state.clone()),
+        ..Default::default()
+    };
+    let (port, base_uri) = if config.dry_run_start_server {
+        let started = launch_server(
+            config.port,
+            Some(flow_id.to_string()),
+            options,
+            SecurityConfig::default(),
+            false,
+            state,
+            Arc::new(app.clone()),
+        )
+        .await?;
+        (Some(started.port), started.redirect_uri)
+    } else if config.port == 0 {
+        return Err("dry_run without starting the server requires a fixed port".into());
+    } else {
+        (None, options.redirect_uri(config.port))
+    };
+    let redirect_uri = format!("{}/{}", base_uri.trim_end_matches('/'), provider);
+    
+    let authorize_url = authorize_url(
+        &config.authorize_url,
+        &config.client_id,
+        &redirect_uri,
+        &config.scopes,
+        &presets::scope_delimiter(Some(&provider), config.scope_delimiter.as_deref()),
+        &oauth_state,
+        Some(&challenge),
+        &HashMap::new(),
+    );
+    let authorize_url = match authorize_url {
+        Ok(url) => url,
+        Err(e) => {
+            if let Some(port) = port {
+                shutdown_server(state, port).await;
+            }
+            return Err(e.into());
+        }
+    };
+    
+    Ok(DryRunResult {
+        flow_id: flow_id.to_string(),
+        authorize_url,
+        redirect_uri,
+        state: oauth_state,
+        code_challenge: challenge,
+        port,
+    })
+}
+
+// 取消进行中的 oauth_login，使其返回 cancelled 错误并停止回调服务器
+#[command]
+pub fn cancel_oauth_login(flow_id: String, state: State<'_, OAuthServerState>) -> Result<(), OAuthError> {
+    lock_recover(&state.logins)
+        .get(&flow_id)
+        .map(CancellationToken::cancel)
+        .ok_or_else(|| OAuthError::NotFound(format!("no login in progress for flow {}", flow_id)))
+}
+
+// 在发起登录前一次性检查配置，返回全部问题，供前端在表单中逐项提示
+#[command]
+pub fn validate_oauth_config(config: OAuthConfig) -> Result<(), Vec<String>> {
+    let mut problems = Vec::new();
+    
+    check_url(&mut problems, "authorize_url", &config.authorize_url);
+    check_url(&mut problems, "token_url", &config.token_url);
+    if let Some(userinfo_url) = &config.userinfo_url {
+        check_url(&mut problems, "userinfo_url", userinfo_url);
+    }
+    
+    if config.client_id.trim().is_empty() {
+        problems.push("client_id must not be empty".to_string());
+    }
+    // oauth_login 总是使用 PKCE，client_secret 可以省略，但提供时不能为空
+    if config.client_secret.as_deref().is_some_and(|secret| secret.trim().is_empty()) {
+        problems.push("client_secret must be omitted rather than empty when the client uses PKCE only".to_string());
+    }
+    
+    // 0 表示由系统分配端口；1024 以下的端口需要管理员权限
+    if config.port != 0 && config.port < 1024 {
+        problems.push(format!("port must be 0 or at least 1024, got {}", config.port));
+    }
+    
+    let provider = config.provider.as_deref();
+    let delimiter = presets::scope_delimiter(provider, config.scope_delimiter.as_deref());
+    match presets::join_scopes(&config.scopes, &delimiter) {
+        Ok(scope) if scope.is_empty() && provider.is_some_and(presets::requires_scopes) => {
+            problems.push(format!("scopes must not be empty for provider {}", provider.unwrap_or_default()));
+        }
+        Ok(_) => {}
+        Err(e) => problems.push(e),
+    }
+    
+    if config.dry_run_start_server && !config.dry_run {
+        problems.push("dry_run_start_server requires dry_run".to_string());
+    }
+    if config.dry_run && !config.dry_run_start_server && config.port == 0 {
+        problems.push("dry_run without starting the server requires a fixed port".to_string());
+    }
+    
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems)
+    }
+}
+
+fn check_url(problems: &mut Vec<String>, field: &str, url: &str) {
+    match Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {}
+        Ok(parsed) => problems.push(format!("{} must use http or https, got {}", field, parsed.scheme())),
+        Err(e) => problems.push(format!("invalid {}: {}", field, e)),
+    }
+}
+
+// 事件经 sink 发出、授权地址经 opener 打开，测试中可以不启动 Tauri 应用而用模拟提供商走完整个流程
+async fn login_flow(
+    config: &OAuthConfig,
+    flow_id: &str,
+    state: &OAuthServerState,
+    client: &reqwest::Client,
+    sink: Arc<dyn CallbackSink>,
+    opener: &dyn AuthorizeOpener,
+) -> Result<LoginResult, OAuthError> {
+    let provider = config
+        .provider
+        .clone()
+        .unwrap_or_else(|| DEFAULT_PROVIDER.to_string());
+    let oauth_state = pkce::random_state();
+    let verifier = pkce::random_verifier();
+    let challenge = pkce::code_challenge(&verifier);
+    
+    let options = ServerOptions {
+        expected_state: Some(oauth_state.clone()),
+        ..Default::default()
+    };
+    let started = launch_server(
+        config.port,
+        Some(flow_id.to_string()),
+        options,
+        SecurityConfig::default(),
+        false,
+        state,
+        sink.clone(),
+    )
+    .await?;
+    let port = started.port;
+    let redirect_uri = format!("{}/{}", started.redirect_uri.trim_end_matches('/'), provider);
+    emit_progress(sink.as_ref(), flow_id, "server_started");
+    
+    let callback = match wait_for_callback(state, port) {
+        Ok(callback) => callback,
+        Err(e) => {
+            shutdown_server(state, port).await;
+            return Err(e);
+        }
+    };
+    
+    let opened = authorize_url(
+        &config.authorize_url,
+        &config.client_id,
+        &redirect_uri,
+        &config.scopes,
+        &presets::scope_delimiter(Some(&provider), config.scope_delimiter.as_deref()),
+        &oauth_state,
+        Some(&challenge),
+        &HashMap::new(),
+    )
+    .and_then(|url| opener.open(&url));
+    if let Err(e) = opened {
+        shutdown_server(state, port).await;
+        return Err(e.into());
+    }
+    emit_progress(sink.as_ref(), flow_id, "browser_opened");
+    
+    let payload = match callback.await {
+        Ok(Ok(payload)) => payload,
+        Ok(Err(payload)) => {
+            // 错误回调不会自动停止服务器，这里由登录流程负责关闭
+            shutdown_server(state, port).await;
+            return Err(callback_error(&payload));
+        }
+        Err(_) => return Err(OAuthError::Cancelled),
+    };
+    emit_progress(sink.as_ref(), flow_id, "callback_received");
+    
+    let code = payload["code"]
+        .as_str()
+        .ok_or_else(|| OAuthError::from("callback did not include an authorization code"))?;
+    emit_progress(sink.as_ref(), flow_id, "exchanging");
+    let tokens = token::exchange_code(
+        client,
+        &config.token_url,
+        &config.client_id,
+        config.client_secret.as_deref(),
+        code,
+        &redirect_uri,
+        Some(&verifier),
+    )
+    .await?;
+    
+    let userinfo = match &config.userinfo_url {
+        Some(userinfo_url) => {
+            let access_token = tokens["access_token"]
+                .as_str()
+                .ok_or_else(|| OAuthError::from("token response did not include an access_token"))?;
+            emit_progress(sink.as_ref(), flow_id, "fetching_userinfo");
+            Some(userinfo::request_userinfo(client, userinfo_url, access_token).await?)
+        }
+        None => None,
+    };
+    emit_progress(sink.as_ref(), flow_id, "done");
+    
+    Ok(LoginResult {
+        flow_id: flow_id.to_string(),
+        provider,
+        tokens,
+        userinfo,
+    })
+}
+
+// 把 oauth-callback-error 载荷转换为错误，提供商给出的 error_description 一并保留，如 "access_denied: The user denied access"
+fn callback_error(payload: &Value) -> OAuthError {
+    let error = payload["error"].as_str().unwrap_or("unknown_error");
+    let message = match payload["error_description"].as_str().filter(|description| !description.is_empty()) {
+        Some(description) => format!("{}: {}", error, description),
+        None => error.to_string(),
+    };
+    if error == "state_mismatch" {
+        return OAuthError::StateMismatch(format!("authorization failed: {}", message));
+    }
+    OAuthError::Provider {
+        status: None,
+        body: message,
+    }
+}
+
+// 通知前端登录流程进入的阶段，便于登录弹窗显示"正在换取令牌"等进度
+fn emit_progress(sink: &dyn CallbackSink, flow_id: &str, stage: &str) {
+    let payload = json!({
+        "flow_id": flow_id,
+        "stage": stage
+    });
+    if let Err(e) = sink.emit("oauth-progress", &payload) {
+        warn!(stage, error = %e, "Failed to emit oauth-progress event");
+    }
+}
+
+// 在系统浏览器中打开授权地址，只允许可信主机上的 http(s) 地址
+// 没有默认浏览器等原因打开失败时返回错误，前端可以改为展示可复制的链接
+#[command]
+pub async fn open_authorize(url: String, client: State<'_, HttpClient>, app: AppHandle) -> Result<(), OAuthError> {
+    open_trusted(&client, &app, &url)
+}
+
+fn open_trusted(client: &HttpClient, opener: &dyn AuthorizeOpener, url: &str) -> Result<(), OAuthError> {
+    client.check_trusted(url)?;
+    Ok(opener.open(url)?)
+}
+
+// 登录流程打开授权地址的方式，应用中为系统浏览器
+pub trait AuthorizeOpener: Send + Sync {
+    fn open(&self, url: &str) -> Result<(), String>;
+}
+
+impl AuthorizeOpener for AppHandle {
+    fn open(&self, url: &str) -> Result<(), String> {
+        open_url(self, url)
+    }
+}
+
+fn open_url(app: &AppHandle, url: &str) -> Result<(), String> {
+    let parsed = Url::parse(url).map_err(|e| format!("invalid authorize URL: {}", e))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(format!("authorize URL must use http or https, got {}", parsed.scheme()));
+    }
+    
+    app.opener()
+        .open_url(parsed.as_str(), None::<&str>)
+        .map_err(|e| format!("failed to open browser: {}", e))
+}
+
+// 构造授权地址：所有参数统一编码，scope 按 scope_delimiter 规范化后连接，省略时使用 provider 对应预设的分隔符（默认空格），
+// 提供 code_challenge 时附带 S256 方法
+// extra 中与标准参数同名的键会覆盖标准参数，其余按键名顺序追加
+#[command]
+#[allow(clippy::too_many_arguments)]
+pub fn build_authorize_url(
+    base: String,
+    client_id: String,
+    redirect_uri: String,
+    scopes: Vec<String>,
+    provider: Option<String>,
+    scope_delimiter: Option<String>,
+    state: String,
+    code_challenge: Option<String>,
+    extra: HashMap<String, String>,
+) -> Result<String, OAuthError> {
+    Ok(authorize_url(
+        &base,
+        &client_id,
+        &redirect_uri,
+        &scopes,
+        &presets::scope_delimiter(provider.as_deref(), scope_delimiter.as_deref()),
+        &state,
+        code_challenge.as_deref(),
+        &extra,
+    )?)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn authorize_url(
+    base: &str,
+    client_id: &str,
+    redirect_uri: &str,
+    scopes: &[String],
+    scope_delimiter: &str,
+    state: &str,
+    code_challenge: Option<&str>,
+    extra: &HashMap<String, String>,
+) -> Result<String, String> {
+    let mut url = Url::parse(base).map_err(|e| format!("invalid authorize_url: {}", e))?;
+    
+    let scope = presets::join_scopes(scopes, scope_delimiter)?;
+    let mut params = vec![
+        ("response_type", "code"),
+        ("client_id", client_id),
+        ("redirect_uri", redirect_uri),
+        ("scope", scope.as_str()),
+        ("state", state),
+    ];
+    if let Some(challenge) = code_challenge {
+        params.push(("code_challenge", challenge));
+        params.push(("code_challenge_method", "S256"));
+    }
+    
+    let mut extra: Vec<(&String, &String)> = extra.iter().collect();
+    extra.sort();
+    for (key, value) in extra {
+        match params.iter_mut().find(|(name, _)| name == key) {
+            Some(param) => param.1 = value,
+            None => params.push((key, value)),
+        }
+    }
+    
+    url.query_pairs_mut().extend_pairs(params);
+    Ok(url.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    
+    use super::*;
+    
+    fn config(authorize_url: String, token_url: String) -> OAuthConfig {
+        OAuthConfig {
+            provider: None,
+            authorize_url,
+            token_url,
+            userinfo_url: None,
+            client_id: "client".to_string(),
+            client_secret: None,
+            scopes: vec!["read".to_string()],
+            scope_delimiter: None,
+            port: 0,
+            dry_run: false,
+            dry_run_start_server: false,
+        }
+    }
+    
+    // 只记录要打开的地址
+    #[derive(Default)]
+    struct RecordingOpener(Mutex<Vec<String>>);
+    
+    impl AuthorizeOpener for RecordingOpener {
+        fn open(&self, url: &str) -> Result<(), String> {
+            lock_recover(&self.0).push(url.to_string());
+            Ok(())
+        }
+    }
+    
+    #[test]
+    fn untrusted_authorize_host_is_rejected() {
+        let client = HttpClient::new().unwrap();
+        client.set_trusted_hosts(&["github.com".to_string()]);
+        
+        let trusted = config("https://github.com/login/oauth/authorize".to_string(), "https://github.com/token".to_string());
+        assert!(check_hosts(&trusted, &client).is_ok());
+        let phishing = config("https://github.example/authorize".to_string(), "https://github.com/token".to_string());
+        let error = check_hosts(&phishing, &client).unwrap_err();
+        assert!(matches!(error, OAuthError::UntrustedHost(_)), "{:?}", error);
+        
+        let opener = RecordingOpener::default();
+        assert!(open_trusted(&client, &opener, "https://github.example/authorize").is_err());
+        open_trusted(&client, &opener, "https://github.com/login/oauth/authorize").unwrap();
+        assert_eq!(*lock_recover(&opener.0), ["https://github.com/login/oauth/authorize"]);
+    }
+    
+    #[test]
+    fn callback_error_keeps_the_description() {
+        let denied = callback_error(&json!({ "error": "access_denied", "error_description": "The user denied access" }));
+        assert_eq!(denied.to_string(), "provider returned an error: access_denied: The user denied access");
+        
+        let bare = callback_error(&json!({ "error": "server_error", "error_description": null }));
+        assert_eq!(bare.to_string(), "provider returned an error: server_error");
+        assert!(matches!(callback_error(&json!({ "error": "state_mismatch" })), OAuthError::StateMismatch(_)));
+    }
+    
+    // 代替浏览器请求授权地址，并跟随模拟提供商的重定向访问回调地址
+    #[cfg(feature = "test-provider")]
+    struct HttpOpener(reqwest::Client);
+    
+    #[cfg(feature = "test-provider")]
+    impl AuthorizeOpener for HttpOpener {
+        fn open(&self, url: &str) -> Result<(), String> {
+            tokio::spawn(self.0.get(url).send());
+            Ok(())
+        }
+    }
+    
+    #[cfg(feature = "test-provider")]
+    #[tokio::test]
+    async fn login_flow_completes_against_mock_provider() {
+        use crate::mock_provider::{MockProvider, MOCK_ACCESS_TOKEN, MOCK_REFRESH_TOKEN};
+        use crate::test_support::RecordingSink;
+        
+        let provider = MockProvider::start().await.unwrap();
+        let config = config(provider.authorize_url(), provider.token_url());
+        let state = OAuthServerState::default();
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        let sink = Arc::new(RecordingSink::default());
+        
+        let result = login_flow(&config, "mock-flow", &state, &client, sink.clone(), &HttpOpener(client.clone()))
+            .await
+            .unwrap();
+        assert_eq!(result.flow_id, "mock-flow");
+        assert_eq!(result.provider, DEFAULT_PROVIDER);
+        assert_eq!(result.tokens["access_token"], MOCK_ACCESS_TOKEN);
+        assert_eq!(result.tokens["refresh_token"], MOCK_REFRESH_TOKEN);
+        assert!(result.userinfo.is_none());
+        
+        let stages: Vec<Value> = sink.events("oauth-progress").iter().map(|payload| payload["stage"].clone()).collect();
+        assert_eq!(stages, ["server_started", "browser_opened", "callback_received", "exchanging", "done"]);
+    }
+}
@@ -0,0 +1,111 @@
+use std::net::{Ipv4Addr, SocketAddr};
+
+use serde_json::json;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+use url::Url;
+
+use crate::http::{http_response, parse_http_request, read_body, read_request_head, write_response, RequestHead};
+
+// 授权端点重定向时附带的固定授权码
+pub const MOCK_CODE: &str = "mock-authorization-code";
+
+// 令牌端点返回的固定令牌
+pub const MOCK_ACCESS_TOKEN: &str = "mock-access-token";
+pub const MOCK_REFRESH_TOKEN: &str = "mock-refresh-token";
+
+// 模拟请求允许的最大字节数
+const MAX_REQUEST_BYTES: usize = 16 * 1024;
+
+// 仅在 test-provider 特性下编译的进程内模拟提供商，供端到端测试在无网络时走完整登录流程：
+// - GET /authorize 立即 302 重定向到 redirect_uri，附带 MOCK_CODE 和原样返回的 state
+// - POST /token 返回固定的令牌 JSON
+// 丢弃时停止监听
+pub struct MockProvider {
+    addr: SocketAddr,
+    shutdown: CancellationToken,
+}
+
+impl MockProvider {
+    // 在 127.0.0.1 的随机端口上启动
+    pub async fn start() -> std::io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let addr = listener.local_addr()?;
+        let shutdown = CancellationToken::new();
+        tokio::spawn(serve(listener, shutdown.clone()));
+        Ok(Self { addr, shutdown })
+    }
+    
+    pub fn authorize_url(&self) -> String {
+        format!("http://{}/authorize", self.addr)
+    }
+    
+    pub fn token_url(&self) -> String {
+        format!("http://{}/token", self.addr)
+    }
+}
+
+impl Drop for MockProvider {
+    fn drop(&mut self) {
+        self.shutdown.cancel();
+    }
+}
+
+async fn serve(listener: TcpListener, shutdown: CancellationToken) {
+    loop {
+        let stream = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    debug!(error = %e, "Mock provider accept failed");
+                    continue;
+                }
+            },
+        };
+        tokio::spawn(handle(stream));
+    }
+}
+
+async fn handle(mut stream: TcpStream) {
+    let buffer = match read_request_head(&mut stream, MAX_REQUEST_BYTES).await {
+        Ok(RequestHead::Complete(buffer)) => buffer,
+        _ => return,
+    };
+    let Ok(request) = parse_http_request(&buffer) else {
+        return;
+    };
+    
+    let response = match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/authorize") => match callback_location(request.query_param("redirect_uri"), request.query_param("state")) {
+            Some(location) => http_response("302 Found", &[("Location", &location)], "text/plain; charset=utf-8", "Found"),
+            None => http_response("400 Bad Request", &[], "text/plain; charset=utf-8", "invalid redirect_uri"),
+        },
+        ("POST", "/token") => {
+            // 读完请求体再响应，避免客户端仍在发送时连接被重置
+            if let Some(length) = request.content_length() {
+                let _ = read_body(&mut stream, &buffer, length.min(MAX_REQUEST_BYTES)).await;
+            }
+            let tokens = json!({
+                "access_token": MOCK_ACCESS_TOKEN,
+                "token_type": "Bearer",
+                "expires_in": 3600,
+                "refresh_token": MOCK_REFRESH_TOKEN
+            });
+            http_response("200 OK", &[], "application/json", &tokens.to_string())
+        }
+        _ => http_response("404 Not Found", &[], "text/plain; charset=utf-8", "Not Found"),
+    };
+    
+    write_response(&mut stream, &response).await;
+}
+
+fn callback_location(redirect_uri: Option<&str>, state: Option<&str>) -> Option<String> {
+    let mut url = Url::parse(redirect_uri?).ok()?;
+    url.query_pairs_mut().append_pair("code", MOCK_CODE);
+    if let Some(state) = state {
+        url.query_pairs_mut().append_pair("state", state);
+    }
+    Some(url.into())
+}
@@ -0,0 +1,55 @@
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+use tauri::{AppHandle, Manager};
+
+use crate::private_file;
+
+// 应用数据目录下保存未送达回调载荷的文件，应用退出时写入，下次启动后取回即删除
+const PENDING_FILE: &str = "oauth_pending_callback.json";
+
+// 退出时把暂存的回调载荷写入磁盘，应用被终止后已完成的授权也不会丢失
+// 载荷中含有授权码，Unix 下文件只允许当前用户读写
+pub fn persist_pending(app: &AppHandle, payload: &Value) -> Result<(), String> {
+    let path = pending_path(app)?;
+    private_file::write_private(&path, payload.to_string().as_bytes(), "pending callback file")
+}
+
+// 取出上次退出时保存的回调载荷，读取后立即删除文件；没有文件时返回 None
+pub fn take_persisted(app: &AppHandle) -> Result<Option<Value>, String> {
+    let path = pending_path(app)?;
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("failed to read pending callback file: {}", e)),
+    };
+    remove_pending(&path)?;
+    
+    serde_json::from_str(&content)
+        .map(Some)
+        .map_err(|e| format!("invalid pending callback file: {}", e))
+}
+
+// 删除保存的回调载荷，返回是否存在
+pub fn clear_persisted(app: &AppHandle) -> Result<bool, String> {
+    let path = pending_path(app)?;
+    let existed = path.exists();
+    remove_pending(&path)?;
+    Ok(existed)
+}
+
+fn remove_pending(path: &Path) -> Result<(), String> {
+    match std::fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(format!("failed to remove pending callback file: {}", e)),
+    }
+}
+
+fn pending_path(app: &AppHandle) -> Result<PathBuf, String> {
+    let dir = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("failed to resolve app data directory: {}", e))?;
+    Ok(dir.join(PENDING_FILE))
+}
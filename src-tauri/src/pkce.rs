@@ -0,0 +1,53 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::Rng;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tauri::command;
+
+use crate::error::OAuthError;
+
+// RFC 7636 规定 code_verifier 只能使用的非保留字符
+const UNRESERVED_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-._~";
+
+// code_verifier 长度，须在 43 到 128 之间
+const VERIFIER_LENGTH: usize = 64;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PkcePair {
+    pub verifier: String,
+    pub challenge: String,
+    pub method: String,
+}
+
+// 生成 PKCE 的 code_verifier 及其 S256 code_challenge，密钥不经过 webview 生成
+#[command]
+pub fn generate_pkce_pair() -> Result<PkcePair, OAuthError> {
+    let verifier = random_verifier();
+    let challenge = code_challenge(&verifier);
+    
+    Ok(PkcePair {
+        verifier,
+        challenge,
+        method: "S256".to_string(),
+    })
+}
+
+// 使用系统 CSPRNG 生成随机 code_verifier
+pub fn random_verifier() -> String {
+    let mut rng = rand::rngs::OsRng;
+    (0..VERIFIER_LENGTH)
+        .map(|_| UNRESERVED_CHARS[rng.gen_range(0..UNRESERVED_CHARS.len())] as char)
+        .collect()
+}
+
+// 生成 URL 安全的随机 state，用于防止 CSRF
+pub fn random_state() -> String {
+    let bytes: [u8; 32] = rand::rngs::OsRng.gen();
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+// code_challenge = base64url(SHA-256(verifier))，不带填充
+pub fn code_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
@@ -0,0 +1,200 @@
+use serde::{Deserialize, Serialize};
+use tauri::command;
+
+use crate::error::OAuthError;
+
+// 内置提供商的标准端点和默认权限范围，字段名与 oauth_login 的配置一致，前端只需补充 client_id / client_secret
+#[derive(Debug, Clone, Serialize)]
+pub struct ProviderPreset {
+    pub provider: String,
+    pub authorize_url: String,
+    pub token_url: String,
+    pub userinfo_url: Option<String>,
+    pub revocation_url: Option<String>,
+    pub device_auth_url: Option<String>,
+    pub scopes: Vec<String>,
+    // 授权地址中连接多个 scope 的分隔符，多数提供商为空格，部分旧式提供商要求逗号
+    pub scope_delimiter: String,
+}
+
+// 覆盖预设中的字段，如自建 GitLab 的地址或不同的权限范围
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PresetOverrides {
+    pub authorize_url: Option<String>,
+    pub token_url: Option<String>,
+    pub userinfo_url: Option<String>,
+    pub revocation_url: Option<String>,
+    pub device_auth_url: Option<String>,
+    pub scopes: Option<Vec<String>>,
+    pub scope_delimiter: Option<String>,
+}
+
+// 返回内置提供商（github、google、gitlab、microsoft）的预设，overrides 中提供的字段优先
+#[command]
+pub fn provider_preset(name: String, overrides: Option<PresetOverrides>) -> Result<ProviderPreset, OAuthError> {
+    let mut preset = preset(&name).ok_or_else(|| OAuthError::NotFound(format!("unknown provider preset: {}", name)))?;
+    
+    let overrides = overrides.unwrap_or_default();
+    if let Some(authorize_url) = overrides.authorize_url {
+        preset.authorize_url = authorize_url;
+    }
+    if let Some(token_url) = overrides.token_url {
+        preset.token_url = token_url;
+    }
+    if let Some(userinfo_url) = overrides.userinfo_url {
+        preset.userinfo_url = Some(userinfo_url);
+    }
+    if let Some(revocation_url) = overrides.revocation_url {
+        preset.revocation_url = Some(revocation_url);
+    }
+    if let Some(device_auth_url) = overrides.device_auth_url {
+        preset.device_auth_url = Some(device_auth_url);
+    }
+    if let Some(scopes) = overrides.scopes {
+        preset.scopes = scopes;
+    }
+    if let Some(scope_delimiter) = overrides.scope_delimiter {
+        preset.scope_delimiter = scope_delimiter;
+    }
+    
+    Ok(preset)
+}
+
+// 内置预设的端点表
+struct Endpoints {
+    name: &'static str,
+    authorize_url: &'static str,
+    token_url: &'static str,
+    userinfo_url: Option<&'static str>,
+    revocation_url: Option<&'static str>,
+    device_auth_url: Option<&'static str>,
+    scopes: &'static [&'static str],
+    scope_delimiter: &'static str,
+}
+
+const PRESETS: &[Endpoints] = &[
+    Endpoints {
+        name: "github",
+        authorize_url: "https://github.com/login/oauth/authorize",
+        token_url: "https://github.com/login/oauth/access_token",
+        userinfo_url: Some("https://api.github.com/user"),
+        revocation_url: None,
+        device_auth_url: Some("https://github.com/login/device/code"),
+        scopes: &["read:user", "user:email"],
+        scope_delimiter: " ",
+    },
+    Endpoints {
+        name: "google",
+        authorize_url: "https://accounts.google.com/o/oauth2/v2/auth",
+        token_url: "https://oauth2.googleapis.com/token",
+        userinfo_url: Some("https://openidconnect.googleapis.com/v1/userinfo"),
+        revocation_url: Some("https://oauth2.googleapis.com/revoke"),
+        device_auth_url: Some("https://oauth2.googleapis.com/device/code"),
+        scopes: &["openid", "email", "profile"],
+        scope_delimiter: " ",
+    },
+    Endpoints {
+        name: "gitlab",
+        authorize_url: "https://gitlab.com/oauth/authorize",
+        token_url: "https://gitlab.com/oauth/token",
+        userinfo_url: Some("https://gitlab.com/oauth/userinfo"),
+        revocation_url: Some("https://gitlab.com/oauth/revoke"),
+        device_auth_url: Some("https://gitlab.com/oauth/authorize_device"),
+        scopes: &["openid", "profile", "email"],
+        scope_delimiter: " ",
+    },
+    Endpoints {
+        name: "microsoft",
+        authorize_url: "https://login.microsoftonline.com/common/oauth2/v2.0/authorize",
+        token_url: "https://login.microsoftonline.com/common/oauth2/v2.0/token",
+        userinfo_url: Some("https://graph.microsoft.com/oidc/userinfo"),
+        revocation_url: None,
+        device_auth_url: Some("https://login.microsoftonline.com/common/oauth2/v2.0/devicecode"),
+        scopes: &["openid", "profile", "email", "offline_access"],
+        scope_delimiter: " ",
+    },
+];
+
+// 预设带有默认权限范围的提供商（如 OpenID Connect 提供商需要 openid）不接受空的 scope
+pub fn requires_scopes(provider: &str) -> bool {
+    endpoints(provider).is_some_and(|endpoints| !endpoints.scopes.is_empty())
+}
+
+fn endpoints(name: &str) -> Option<&'static Endpoints> {
+    let name = name.trim();
+    PRESETS.iter().find(|preset| preset.name.eq_ignore_ascii_case(name))
+}
+
+fn preset(name: &str) -> Option<ProviderPreset> {
+    let endpoints = endpoints(name)?;
+    
+    Some(ProviderPreset {
+        provider: endpoints.name.to_string(),
+        authorize_url: endpoints.authorize_url.to_string(),
+        token_url: endpoints.token_url.to_string(),
+        userinfo_url: endpoints.userinfo_url.map(str::to_string),
+        revocation_url: endpoints.revocation_url.map(str::to_string),
+        device_auth_url: endpoints.device_auth_url.map(str::to_string),
+        scopes: endpoints.scopes.iter().map(|scope| scope.to_string()).collect(),
+        scope_delimiter: endpoints.scope_delimiter.to_string(),
+    })
+}
+
+// 连接 scope 使用的分隔符：显式传入的优先（如 PresetOverrides.scope_delimiter），其次是提供商预设，未知提供商使用空格
+pub fn scope_delimiter(provider: Option<&str>, explicit: Option<&str>) -> String {
+    explicit
+        .or_else(|| provider.and_then(endpoints).map(|endpoints| endpoints.scope_delimiter))
+        .unwrap_or(" ")
+        .to_string()
+}
+
+// 规范化权限范围并用 delimiter 连接，delimiter 通常来自 scope_delimiter
+// 每项先去掉首尾空白，重复的 scope 只保留第一次出现；只有分隔符为空格时才把误用逗号连接的项拆开，
+// 逗号分隔的提供商不会出现这种误用，而其他分隔符下逗号可能是 scope 本身的一部分
+// 单个 scope 只允许 RFC 6749 规定的可见 ASCII 字符，含空白、`"` 或 `\` 时返回错误
+pub fn join_scopes(scopes: &[String], delimiter: &str) -> Result<String, String> {
+    let split_commas = delimiter == " ";
+    let mut normalized: Vec<&str> = Vec::new();
+    let items = scopes.iter().flat_map(|scope| match split_commas {
+        true => scope.split(',').collect(),
+        false => vec![scope.as_str()],
+    });
+    for scope in items.map(str::trim) {
+        if scope.is_empty() || normalized.contains(&scope) {
+            continue;
+        }
+        if scope.contains(char::is_whitespace) {
+            return Err(format!("scope must not contain whitespace: {:?}", scope));
+        }
+        if !scope.bytes().all(|b| (0x21..=0x7e).contains(&b) && b != b'"' && b != b'\\') {
+            return Err(format!("scope contains invalid characters: {:?}", scope));
+        }
+        normalized.push(scope);
+    }
+    
+    Ok(normalized.join(delimiter))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn scopes(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+    
+    #[test]
+    fn explicit_delimiter_overrides_preset() {
+        assert_eq!(scope_delimiter(Some("github"), None), " ");
+        assert_eq!(scope_delimiter(Some("github"), Some(",")), ",");
+        assert_eq!(scope_delimiter(Some("unknown"), None), " ");
+    }
+    
+    #[test]
+    fn commas_are_split_only_for_space_delimiter() {
+        assert_eq!(join_scopes(&scopes(&["read:user,user:email", "repo"]), " ").unwrap(), "read:user user:email repo");
+        assert_eq!(join_scopes(&scopes(&["a,b", "c"]), ",").unwrap(), "a,b,c");
+        assert_eq!(join_scopes(&scopes(&["a,b", "c"]), "+").unwrap(), "a,b+c");
+    }
+}
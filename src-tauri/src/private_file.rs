@@ -0,0 +1,77 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// 原子地写入只允许当前用户读写的文件（Unix 下为 0600），用于保存含有授权码或 code_verifier 的状态
+// 先写入同目录下的临时文件并落盘，再重命名覆盖目标文件：中途崩溃或写入失败时旧文件保持完整，
+// 新文件也不会沿用旧文件的权限；what 用于错误信息，如 "flow file"
+pub fn write_private(path: &Path, content: &[u8], what: &str) -> Result<(), String> {
+    let dir = path.parent().ok_or_else(|| format!("invalid {} path: {}", what, path.display()))?;
+    std::fs::create_dir_all(dir).map_err(|e| format!("failed to create app data directory: {}", e))?;
+    
+    // 上次崩溃可能留下临时文件，删除后再以 create_new 新建，确保使用下面的权限
+    let temp = temp_path(path);
+    remove_if_exists(&temp).map_err(|e| format!("failed to remove stale temporary {}: {}", what, e))?;
+    
+    let written = write_new(&temp, content).and_then(|()| std::fs::rename(&temp, path));
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&temp);
+        return Err(format!("failed to write {}: {}", what, e));
+    }
+    
+    // 重命名本身也需要落盘；部分平台无法打开目录，此时忽略
+    #[cfg(unix)]
+    if let Ok(dir) = std::fs::File::open(dir) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+fn write_new(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    file.write_all(content)?;
+    file.sync_all()
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn replaces_the_file_without_leaving_a_temporary() {
+        let dir = std::env::temp_dir().join(format!("oauth-private-file-test-{}", std::process::id()));
+        let path = dir.join("state.json");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(temp_path(&path), "stale").unwrap();
+        
+        write_private(&path, b"old", "state file").unwrap();
+        write_private(&path, b"new", "state file").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        assert!(!temp_path(&path).exists());
+        
+        // 写入失败时原文件保持不变
+        std::fs::create_dir(temp_path(&path)).unwrap();
+        assert!(write_private(&path, b"lost", "state file").is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
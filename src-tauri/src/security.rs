@@ -0,0 +1,116 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+
+// 读取单个连接请求的默认超时时间
+const DEFAULT_READ_TIMEOUT_SECS: u64 = 30;
+
+// 连接建立后等待第一个字节的默认超时时间。浏览器的预连接可能一直空闲，
+// 若按 read_timeout 等待，几个空闲连接就能占满全部连接名额；提前关闭是安全的，
+// 浏览器发现空闲连接被关闭后会新建连接重发请求
+const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 2;
+
+// 默认同时处理的最大连接数，高于浏览器对单个主机的并发连接数（通常为 6），
+// 回调页面、favicon 和预连接不会互相排队，也防止本机异常进程打开大量连接
+const DEFAULT_MAX_CONNECTIONS: usize = 8;
+
+// 单个请求默认允许的最大字节数，防止异常客户端无限写入
+const DEFAULT_MAX_REQUEST_BYTES: usize = 8 * 1024;
+
+// 每个来源每秒允许的回调次数及突发上限，超出时返回 429
+const DEFAULT_RATE_LIMIT_PER_SEC: f64 = 5.0;
+const DEFAULT_RATE_LIMIT_BURST: f64 = 5.0;
+
+// 等待发送的回调事件队列的默认容量
+const DEFAULT_EMIT_QUEUE_CAPACITY: usize = 16;
+
+// 回调服务器的防滥用限制，作为 start_oauth_server 的可选参数按服务器保存
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct SecurityConfig {
+    // 单个请求（请求头和表单请求体）允许的最大字节数，超出时返回 413
+    pub max_request_bytes: usize,
+    // 同时处理的最大连接数
+    pub max_connections: usize,
+    // 单个连接读取请求的超时时间（秒）
+    pub read_timeout_secs: u64,
+    // 连接建立后等待第一个字节的超时时间（秒），不超过 read_timeout_secs
+    pub idle_timeout_secs: u64,
+    // 每个来源每秒允许的回调次数
+    pub rate_limit_per_sec: f64,
+    // 每个来源允许的突发回调次数
+    pub rate_limit_burst: f64,
+    // 等待发送给前端的回调事件队列容量，队列满时连接处理方等待，避免突发回调淹没前端
+    pub emit_queue_capacity: usize,
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        Self {
+            max_request_bytes: DEFAULT_MAX_REQUEST_BYTES,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            read_timeout_secs: DEFAULT_READ_TIMEOUT_SECS,
+            idle_timeout_secs: DEFAULT_IDLE_TIMEOUT_SECS,
+            rate_limit_per_sec: DEFAULT_RATE_LIMIT_PER_SEC,
+            rate_limit_burst: DEFAULT_RATE_LIMIT_BURST,
+            emit_queue_capacity: DEFAULT_EMIT_QUEUE_CAPACITY,
+        }
+    }
+}
+
+impl SecurityConfig {
+    // 拒绝会让服务器无法工作或失去保护的取值
+    pub fn validate(&self) -> Result<(), String> {
+        if !(512..=1024 * 1024).contains(&self.max_request_bytes) {
+            return Err(format!(
+                "max_request_bytes must be between 512 and 1048576, got {}",
+                self.max_request_bytes
+            ));
+        }
+        if !(1..=1024).contains(&self.max_connections) {
+            return Err(format!(
+                "max_connections must be between 1 and 1024, got {}",
+                self.max_connections
+            ));
+        }
+        if !(1..=300).contains(&self.read_timeout_secs) {
+            return Err(format!(
+                "read_timeout_secs must be between 1 and 300, got {}",
+                self.read_timeout_secs
+            ));
+        }
+        if !(1..=self.read_timeout_secs).contains(&self.idle_timeout_secs) {
+            return Err(format!(
+                "idle_timeout_secs must be between 1 and read_timeout_secs, got {}",
+                self.idle_timeout_secs
+            ));
+        }
+        if !(self.rate_limit_per_sec > 0.0 && self.rate_limit_per_sec <= 1000.0) {
+            return Err(format!(
+                "rate_limit_per_sec must be greater than 0 and at most 1000, got {}",
+                self.rate_limit_per_sec
+            ));
+        }
+        if !(1.0..=1000.0).contains(&self.rate_limit_burst) {
+            return Err(format!(
+                "rate_limit_burst must be between 1 and 1000, got {}",
+                self.rate_limit_burst
+            ));
+        }
+        if !(1..=1024).contains(&self.emit_queue_capacity) {
+            return Err(format!(
+                "emit_queue_capacity must be between 1 and 1024, got {}",
+                self.emit_queue_capacity
+            ));
+        }
+        Ok(())
+    }
+    
+    pub fn read_timeout(&self) -> Duration {
+        Duration::from_secs(self.read_timeout_secs)
+    }
+    
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.idle_timeout_secs)
+    }
+}
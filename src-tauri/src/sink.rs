@@ -0,0 +1,37 @@
+use serde_json::Value;
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::{lock_recover, OAuthServerState, EVENT_HISTORY_LIMIT};
+
+// 回调服务器事件的去向。服务器只依赖该接口而不是 AppHandle，
+// 这样不启动完整的 Tauri 应用也能驱动服务器并记录发出的事件
+// lib.rs 不引入该 trait，避免与 Emitter::emit 冲突，统一通过 dyn CallbackSink 调用
+pub trait CallbackSink: Send + Sync {
+    // 发送 oauth-server-ready、oauth-callback 等服务器事件
+    fn emit(&self, event: &str, payload: &Value) -> Result<(), String>;
+    
+    // 暂存未能送达的回调载荷，等待前端通过 take_pending_oauth_callback 取回
+    fn buffer(&self, payload: Value);
+    
+    // 记录到最近事件历史中，无论是否送达
+    fn record(&self, payload: &Value);
+}
+
+impl CallbackSink for AppHandle {
+    fn emit(&self, event: &str, payload: &Value) -> Result<(), String> {
+        Emitter::emit(self, event, payload).map_err(|e| e.to_string())
+    }
+    
+    fn buffer(&self, payload: Value) {
+        *lock_recover(&self.state::<OAuthServerState>().pending_callback) = Some(payload);
+    }
+    
+    fn record(&self, payload: &Value) {
+        let state = self.state::<OAuthServerState>();
+        let mut history = lock_recover(&state.event_history);
+        if history.len() == EVENT_HISTORY_LIMIT {
+            history.pop_back();
+        }
+        history.push_front(payload.clone());
+    }
+}
@@ -0,0 +1,101 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::sink::CallbackSink;
+use crate::{launch_server, lock_recover, OAuthServerState, SecurityConfig, ServerOptions};
+
+// 记录服务器发出的全部事件，代替 AppHandle 驱动服务器
+#[derive(Default)]
+pub struct RecordingSink {
+    events: Mutex<Vec<(String, Value)>>,
+    // 每次发送前阻塞的时间，模拟处理缓慢的前端；非零时需要多线程运行时
+    delay: Duration,
+}
+
+impl RecordingSink {
+    pub fn with_delay(delay: Duration) -> Self {
+        Self {
+            delay,
+            ..Default::default()
+        }
+    }
+    
+    // 指定事件的全部载荷，按发送顺序排列
+    pub fn events(&self, event: &str) -> Vec<Value> {
+        lock_recover(&self.events)
+            .iter()
+            .filter(|(name, _)| name == event)
+            .map(|(_, payload)| payload.clone())
+            .collect()
+    }
+    
+    // 等待指定事件至少出现 count 次，超时后返回已记录的载荷
+    pub async fn wait_for(&self, event: &str, count: usize) -> Vec<Value> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let events = self.events(event);
+            if events.len() >= count || Instant::now() >= deadline {
+                return events;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+}
+
+impl CallbackSink for RecordingSink {
+    fn emit(&self, event: &str, payload: &Value) -> Result<(), String> {
+        // block_in_place 把当前工作线程上排队的任务交给其他线程，阻塞期间其余连接照常处理
+        if !self.delay.is_zero() {
+            tokio::task::block_in_place(|| std::thread::sleep(self.delay));
+        }
+        lock_recover(&self.events).push((event.to_string(), payload.clone()));
+        Ok(())
+    }
+    
+    // 发送总是成功，不会有需要暂存的载荷
+    fn buffer(&self, _payload: Value) {}
+    
+    fn record(&self, _payload: &Value) {}
+}
+
+// 只监听 127.0.0.1，避免测试依赖 IPv6 回环地址
+pub fn loopback_options() -> ServerOptions {
+    ServerOptions {
+        host: Some("127.0.0.1".to_string()),
+        ..Default::default()
+    }
+}
+
+// 在系统分配的端口上启动服务器，返回端口、事件记录和服务器状态
+pub async fn start_server(
+    options: ServerOptions,
+    security: SecurityConfig,
+    sink: RecordingSink,
+) -> (u16, Arc<RecordingSink>, OAuthServerState) {
+    let sink = Arc::new(sink);
+    let state = OAuthServerState::default();
+    let started = launch_server(0, Some("test-flow".to_string()), options, security, false, &state, sink.clone())
+        .await
+        .expect("failed to start test server");
+    (started.port, sink, state)
+}
+
+pub fn get(target: &str) -> String {
+    format!("GET {} HTTP/1.1\r\nHost: 127.0.0.1\r\nConnection: close\r\n\r\n", target)
+}
+
+// 发送原始请求并读取完整响应，服务器写完响应后会关闭连接
+pub async fn send_request(port: u16, request: &str) -> String {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).await.expect("failed to connect");
+    stream.write_all(request.as_bytes()).await.expect("failed to write request");
+    let mut response = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut response))
+        .await
+        .expect("timed out reading response")
+        .expect("failed to read response");
+    String::from_utf8_lossy(&response).into_owned()
+}
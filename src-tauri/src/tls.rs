@@ -0,0 +1,28 @@
+use std::sync::Arc;
+
+use rcgen::CertifiedKey;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+
+// 为要求 https 回调地址的提供商生成自签名的 localhost 证书
+// 证书每次启动服务器时重新生成，提供商需要接受自签名证书，或由用户在浏览器中确认一次安全警告
+pub fn self_signed_acceptor() -> Result<TlsAcceptor, String> {
+    let CertifiedKey { cert, key_pair } = rcgen::generate_simple_self_signed(vec![
+        "localhost".to_string(),
+        "127.0.0.1".to_string(),
+        "::1".to_string(),
+    ])
+    .map_err(|e| format!("failed to generate TLS certificate: {}", e))?;
+    
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key_pair.serialize_der()));
+    let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("failed to configure TLS: {}", e))?
+        .with_no_client_auth()
+        .with_single_cert(vec![cert.der().clone()], key)
+        .map_err(|e| format!("failed to configure TLS: {}", e))?;
+    
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
@@ -0,0 +1,323 @@
+use std::time::Duration;
+
+use rand::Rng;
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use reqwest::StatusCode;
+use serde_json::{Map, Value};
+use tauri::{command, State};
+use tracing::warn;
+
+use crate::client::HttpClient;
+use crate::error::OAuthError;
+use crate::http::parse_query;
+use crate::keychain;
+
+// 令牌请求在网络错误时的最大尝试次数
+const MAX_ATTEMPTS: u32 = 3;
+
+// 首次重试前的等待时间，之后每次翻倍
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(250);
+
+// 重试等待时间上附加的随机抖动上限（毫秒）
+const RETRY_JITTER_MS: u64 = 100;
+
+// 表单编码的响应中需要转换为数字的字段，与 JSON 响应中的类型保持一致
+const NUMERIC_FIELDS: &[&str] = &["expires_in", "refresh_token_expires_in", "interval"];
+
+// 使用授权码换取令牌，由 Rust 侧完成交换以免在前端暴露 client_secret
+#[command]
+pub async fn exchange_oauth_code(
+    token_url: String,
+    client_id: String,
+    client_secret: Option<String>,
+    code: String,
+    redirect_uri: String,
+    code_verifier: Option<String>,
+    client: State<'_, HttpClient>,
+) -> Result<Value, OAuthError> {
+    client.check_trusted(&token_url)?;
+    exchange_code(
+        &client.get(),
+        &token_url,
+        &client_id,
+        client_secret.as_deref(),
+        &code,
+        &redirect_uri,
+        code_verifier.as_deref(),
+    )
+    .await
+}
+
+pub async fn exchange_code(
+    client: &reqwest::Client,
+    token_url: &str,
+    client_id: &str,
+    client_secret: Option<&str>,
+    code: &str,
+    redirect_uri: &str,
+    code_verifier: Option<&str>,
+) -> Result<Value, OAuthError> {
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", redirect_uri),
+        ("client_id", client_id),
+    ];
+    if let Some(secret) = client_secret {
+        form.push(("client_secret", secret));
+    }
+    // PKCE 流程需要携带 code_verifier
+    if let Some(verifier) = code_verifier {
+        form.push(("code_verifier", verifier));
+    }
+    
+    request_token(client, token_url, &form).await
+}
+
+// 使用刷新令牌换取新的访问令牌，指定 provider 时同时更新钥匙串中保存的令牌
+#[command]
+pub async fn refresh_oauth_token(
+    token_url: String,
+    client_id: String,
+    client_secret: Option<String>,
+    refresh_token: String,
+    provider: Option<String>,
+    client: State<'_, HttpClient>,
+) -> Result<Value, OAuthError> {
+    client.check_trusted(&token_url)?;
+    refresh_tokens(
+        &client.get(),
+        &token_url,
+        &client_id,
+        client_secret.as_deref(),
+        &refresh_token,
+        provider.as_deref(),
+    )
+    .await
+}
+
+pub async fn refresh_tokens(
+    client: &reqwest::Client,
+    token_url: &str,
+    client_id: &str,
+    client_secret: Option<&str>,
+    refresh_token: &str,
+    provider: Option<&str>,
+) -> Result<Value, OAuthError> {
+    let mut form = vec![
+        ("grant_type", "refresh_token"),
+        ("refresh_token", refresh_token),
+        ("client_id", client_id),
+    ];
+    if let Some(secret) = client_secret {
+        form.push(("client_secret", secret));
+    }
+    
+    let mut tokens = request_token(client, token_url, &form).await?;
+    
+    // 不轮换刷新令牌的提供商不会返回新的 refresh_token，沿用原来的
+    if let Some(object) = tokens.as_object_mut() {
+        object
+            .entry("refresh_token")
+            .or_insert_with(|| Value::String(refresh_token.to_string()));
+    }
+    
+    // 没有 access_token 的响应不能覆盖钥匙串中仍然有效的令牌
+    if !tokens["access_token"].is_string() {
+        return Err(OAuthError::Provider {
+            status: None,
+            body: format!("token response did not include an access_token: {}", tokens),
+        });
+    }
+    if let Some(provider) = provider {
+        keychain::save_tokens(provider, &tokens)?;
+    }
+    
+    Ok(tokens)
+}
+
+// 按 RFC 7009 在提供商处撤销令牌，成功后删除钥匙串中保存的令牌
+// 提供商不支持撤销（返回 404 或 400）时同样视为已在本地清除，其他状态码作为错误返回以便前端重试
+#[command]
+pub async fn revoke_oauth_token(
+    revocation_url: String,
+    client_id: String,
+    token: String,
+    token_type_hint: Option<String>,
+    provider: Option<String>,
+    client: State<'_, HttpClient>,
+) -> Result<(), OAuthError> {
+    client.check_trusted(&revocation_url)?;
+    let mut form = vec![("token", token.as_str()), ("client_id", client_id.as_str())];
+    if let Some(hint) = token_type_hint.as_deref() {
+        form.push(("token_type_hint", hint));
+    }
+    
+    let (status, body) = post_form(&client.get(), &revocation_url, &form).await?;
+    match status {
+        StatusCode::OK => {}
+        StatusCode::BAD_REQUEST | StatusCode::NOT_FOUND => {
+            warn!(status = status.as_u16(), "Provider did not revoke token, clearing it locally");
+        }
+        _ => {
+            return Err(OAuthError::Provider {
+                status: Some(status.as_u16()),
+                body,
+            })
+        }
+    }
+    
+    if let Some(provider) = provider {
+        keychain::delete_tokens(&provider)?;
+    }
+    Ok(())
+}
+
+// 向令牌端点提交表单并解析 JSON 响应，非 2xx 时带上提供商返回的错误内容
+async fn request_token(
+    client: &reqwest::Client,
+    token_url: &str,
+    form: &[(&str, &str)],
+) -> Result<Value, OAuthError> {
+    let (status, body) = post_form(client, token_url, form).await?;
+    token_response(status, body)
+}
+
+// GitHub 等提供商以 200 和 error 字段报告失败，与 device.rs 一样先检查 error 字段再看状态码
+fn token_response(status: StatusCode, body: String) -> Result<Value, OAuthError> {
+    match provider_error(&body).as_deref() {
+        // invalid_grant 表示授权码或刷新令牌已失效，前端需要引导用户重新登录
+        Some("invalid_grant") => return Err(OAuthError::TokenExpired(format!("invalid_grant: {}", body))),
+        Some(_) => {
+            return Err(OAuthError::Provider {
+                status: Some(status.as_u16()),
+                body,
+            })
+        }
+        None if !status.is_success() => {
+            return Err(OAuthError::Provider {
+                status: Some(status.as_u16()),
+                body,
+            })
+        }
+        None => {}
+    }
+    
+    serde_json::from_str(&body).map_err(|e| OAuthError::Internal(format!("invalid token response: {}", e)))
+}
+
+// 提交表单并返回状态码和响应正文，由调用方决定如何解释错误
+// 部分提供商（如旧版 GitHub）忽略 Accept 头返回表单编码的正文，这类响应转换为等价的 JSON 文本，
+// 调用方因此可以统一按 JSON 解析令牌和错误
+// 连接失败或超时时按指数退避加随机抖动重试；收到任何 HTTP 响应（包括 4xx）都不重试，
+// 因为授权码可能已被消费
+pub async fn post_form(
+    client: &reqwest::Client,
+    url: &str,
+    form: &[(&str, &str)],
+) -> Result<(StatusCode, String), OAuthError> {
+    let mut attempt = 1;
+    let response = loop {
+        let result = client
+            .post(url)
+            .header(ACCEPT, "application/json")
+            .form(form)
+            .send()
+            .await;
+        match result {
+            Ok(response) => break response,
+            Err(e) if (e.is_connect() || e.is_timeout()) && attempt < MAX_ATTEMPTS => {
+                let jitter = rand::thread_rng().gen_range(0..RETRY_JITTER_MS);
+                let delay = RETRY_BASE_DELAY * 2u32.pow(attempt - 1) + Duration::from_millis(jitter);
+                warn!(attempt, error = %e, "Token request failed, retrying");
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => {
+                return Err(OAuthError::Network(format!(
+                    "token request failed after {} attempt(s): {}",
+                    attempt, e
+                )))
+            }
+        }
+    };
+    
+    let status = response.status();
+    let form_encoded = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/x-www-form-urlencoded"));
+    let body = response
+        .text()
+        .await
+        .map_err(|e| OAuthError::Network(format!("failed to read token response: {}", e)))?;
+    
+    if form_encoded {
+        return Ok((status, form_to_json(&body).to_string()));
+    }
+    Ok((status, body))
+}
+
+// 把表单编码的正文转换为 JSON 对象，重复的键取第一个值，数值字段转换为数字
+fn form_to_json(body: &str) -> Value {
+    let fields: Map<String, Value> = parse_query(body.trim().as_bytes())
+        .into_iter()
+        .filter_map(|(key, values)| {
+            let value = values.into_iter().next()?;
+            let value = match value.parse::<u64>() {
+                Ok(number) if NUMERIC_FIELDS.contains(&key.as_str()) => Value::from(number),
+                _ => Value::String(value),
+            };
+            Some((key, value))
+        })
+        .collect();
+    Value::Object(fields)
+}
+
+// 提取提供商错误响应中的 error 字段
+pub fn provider_error(body: &str) -> Option<String> {
+    serde_json::from_str::<Value>(body)
+        .ok()?
+        .get("error")?
+        .as_str()
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    
+    #[test]
+    fn form_response_becomes_json() {
+        let body = "access_token=gho_abc&token_type=bearer&scope=repo%2Cuser&expires_in=28800&scope=ignored\n";
+        assert_eq!(
+            form_to_json(body),
+            json!({
+                "access_token": "gho_abc",
+                "token_type": "bearer",
+                "scope": "repo,user",
+                "expires_in": 28800
+            })
+        );
+        
+        // 只有已知的数值字段转换为数字，数值字段的非数字取值保留为字符串
+        assert_eq!(form_to_json("interval=5&code=123&expires_in=soon"), json!({ "interval": 5, "code": "123", "expires_in": "soon" }));
+        assert_eq!(form_to_json("error=bad_verification_code"), json!({ "error": "bad_verification_code" }));
+    }
+    
+    #[test]
+    fn error_field_in_success_response_is_an_error() {
+        let body = form_to_json("error=bad_verification_code&error_description=The+code+is+incorrect").to_string();
+        let error = token_response(StatusCode::OK, body).unwrap_err();
+        assert!(matches!(error, OAuthError::Provider { status: Some(200), .. }), "{:?}", error);
+        
+        let expired = token_response(StatusCode::OK, r#"{"error":"invalid_grant"}"#.to_string()).unwrap_err();
+        assert!(matches!(expired, OAuthError::TokenExpired(_)), "{:?}", expired);
+        
+        let tokens = token_response(StatusCode::OK, r#"{"access_token":"abc"}"#.to_string()).unwrap();
+        assert_eq!(tokens["access_token"], "abc");
+    }
+}
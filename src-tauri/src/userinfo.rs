@@ -0,0 +1,52 @@
+use reqwest::header::ACCEPT;
+use reqwest::StatusCode;
+use serde_json::Value;
+use tauri::{command, State};
+
+use crate::client::HttpClient;
+use crate::error::OAuthError;
+
+// 获取提供商 userinfo 端点返回的用户资料（昵称、头像等）
+// 访问令牌失效时返回 "token_expired"，前端据此刷新令牌
+#[command]
+pub async fn fetch_userinfo(
+    userinfo_url: String,
+    access_token: String,
+    client: State<'_, HttpClient>,
+) -> Result<Value, OAuthError> {
+    client.check_trusted(&userinfo_url)?;
+    request_userinfo(&client.get(), &userinfo_url, &access_token).await
+}
+
+pub async fn request_userinfo(
+    client: &reqwest::Client,
+    userinfo_url: &str,
+    access_token: &str,
+) -> Result<Value, OAuthError> {
+    let response = client
+        .get(userinfo_url)
+        .bearer_auth(access_token)
+        .header(ACCEPT, "application/json")
+        .send()
+        .await
+        .map_err(|e| OAuthError::Network(format!("userinfo request failed: {}", e)))?;
+    
+    let status = response.status();
+    if status == StatusCode::UNAUTHORIZED {
+        return Err(OAuthError::TokenExpired("token_expired".to_string()));
+    }
+    
+    let body = response
+        .text()
+        .await
+        .map_err(|e| OAuthError::Network(format!("failed to read userinfo response: {}", e)))?;
+    
+    if !status.is_success() {
+        return Err(OAuthError::Provider {
+            status: Some(status.as_u16()),
+            body,
+        });
+    }
+    
+    serde_json::from_str(&body).map_err(|e| OAuthError::Internal(format!("invalid userinfo response: {}", e)))
+}